@@ -1,16 +1,12 @@
-use crate::expr::{Visitor,Assign,  Expr, Binary, Grouping, Unary, Variable, walk_expr, Logical};
-use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While};
+use crate::expr::{Visitor,Assign,  Expr, Binary, Grouping, Unary, Variable, walk_expr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While, Test, Function, Return};
 use crate::token::{Literal};
 pub struct AstPrinter;
 
 
 impl StmtVisitor<String> for AstPrinter {
     fn visit_block_stmt(&self, e: &Block) -> String {
-        let mut s = String::new();
-        for i in self.print_stmts(&e.statements){
-            s = s + i.as_str();
-        }
-        s
+        format!("{{ {} }}", self.print_stmts(&e.statements).join("; "))
     }
 
     fn visit_expression(&self, e: &Expression) -> String {
@@ -28,12 +24,29 @@ impl StmtVisitor<String> for AstPrinter {
     fn visit_if_stmt(&self, e: &If) -> String {
         let mut s = format!("if ({}) {}", self.print(&e.condition), walk_stmt(self, &e.then_branch));
         // if else exists then mutate s and add the esle to it
-        e.else_branch.as_ref().map(|e| s = format!("{} else {}", s, walk_stmt(self, &e)));
+        if let Some(else_branch) = &e.else_branch {
+            s = format!("{} else {}", s, walk_stmt(self, else_branch));
+        }
         s
     }
 
     fn visit_while_stmt(&self, e: &While) -> String {
-        format!("if ({}) {}", self.print(&e.condition), walk_stmt(self, &e.body))
+        format!("while ({}) {}", self.print(&e.condition), walk_stmt(self, &e.body))
+    }
+
+    fn visit_test_stmt(&self, e: &Test) -> String {
+        format!("test {} {{ {} }}", e.name.lexeme, self.print_stmts(&e.body).join("; "))
+    }
+
+    fn visit_function_stmt(&self, e: &Function) -> String {
+        format!("fun {}{} {{ {} }}", e.name.lexeme, self.params(&e.lambda), self.print_stmts(&e.lambda.body).join("; "))
+    }
+
+    fn visit_return_stmt(&self, e: &Return) -> String {
+        match &e.value {
+            Some(value) => format!("return {}", self.print(value)),
+            None => "return".to_string(),
+        }
     }
 }
 
@@ -51,6 +64,7 @@ impl Visitor<String> for AstPrinter{
     fn visit_literalexp(&self, e: &Literal) -> String {
         match e {
             Literal::Nil => "nil".to_string(),
+            Literal::String(s) => format!("\"{}\"", s),
             _ => e.to_string()
         }
     }
@@ -61,46 +75,119 @@ impl Visitor<String> for AstPrinter{
     }
 
     fn visit_variableexp(&self, e: &Variable) -> String {
-        format!("var {} =", e.name.lexeme)
+        e.name.lexeme.to_string()
     }
 
     fn visit_assignexp(&self, e: &Assign) -> String {
-        format!("var {} =", e.name.lexeme)
+        self.paranthesize(&format!("= {}", e.name.lexeme), vec![&e.value])
+    }
+
+    fn visit_updateexp(&self, e: &Update) -> String {
+        match (&e.value, e.prefix) {
+            (Some(value), _) => self.paranthesize(&format!("{} {}", e.op.lexeme, e.name.lexeme), vec![value]),
+            (None, true) => format!("({} {})", e.op.lexeme, e.name.lexeme),
+            (None, false) => format!("(post{} {})", e.op.lexeme, e.name.lexeme),
+        }
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) -> String {
+        self.paranthesize("?:", vec![&e.condition, &e.then_branch, &e.else_branch])
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) -> String {
+        self.paranthesize(",", vec![&e.left, &e.right])
     }
 
     fn visit_logicalexp(&self, e: &Logical) -> String {
         self.paranthesize(&e.condition.lexeme, vec![&e.left, &e.right])
     }
 
+    fn visit_callexp(&self, e: &Call) -> String {
+        let mut s = format!("(call {}", self.print(&e.callee));
+        for argument in &e.arguments {
+            match &argument.name {
+                Some(name) => s.push_str(&format!(" {}: {}", name.lexeme, self.print(&argument.value))),
+                None => s.push_str(&format!(" {}", self.print(&argument.value))),
+            }
+        }
+        s.push(')');
+        s
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) -> String {
+        format!("(lambda {} {{ {} }})", self.params(e), self.print_stmts(&e.body).join("; "))
+    }
+
+    fn visit_getexp(&self, e: &Get) -> String {
+        format!("(. {} {})", self.print(&e.object), e.name.lexeme)
+    }
+
+    fn visit_indexexp(&self, e: &Index) -> String {
+        self.paranthesize("[]", vec![&e.object, &e.index])
+    }
+
 }
 
 
 impl AstPrinter {
-    pub fn print_stmts(&self, stmts: &Vec<Stmt>) -> Vec<String>{
+    pub fn print_head(&self, stmt: &Stmt) -> String {
+        // just the statement itself, without the statements nested in it
+        match stmt {
+            Stmt::Block(_) => "{".to_string(),
+            Stmt::If(e) => format!("if ({})", self.print(&e.condition)),
+            Stmt::While(e) => format!("while ({})", self.print(&e.condition)),
+            Stmt::Test(e) => format!("test {}", e.name.lexeme),
+            Stmt::Function(e) => format!("fun {}{}", e.name.lexeme, self.params(&e.lambda)),
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    pub fn print_stmts(&self, stmts: &[Stmt]) -> Vec<String>{
         let mut exprs = Vec::new();
         for statement in stmts{
-            exprs.push(walk_stmt(self, &statement))
+            exprs.push(walk_stmt(self, statement))
         }
         exprs
     }
 
 
     pub fn print(&self, expr: &Expr) -> String{
-        return walk_expr(self, expr)
+        walk_expr(self, expr)
+    }
+
+    fn params(&self, lambda: &Lambda) -> String {
+        let params: Vec<String> = lambda.params.iter().map(|p| match (&p.default, p.rest) {
+            (Some(default), _) => format!("(= {} {})", p.name.lexeme, self.print(default)),
+            (None, true) => format!("...{}", p.name.lexeme),
+            (None, false) => p.name.lexeme.clone(),
+        }).collect();
+        format!("({})", params.join(" "))
     }
 
     fn paranthesize(&self, name: &str, exprs: Vec<&Expr>) -> String{
         let mut s = String::new();
 
-        s.push_str("(");
+        s.push('(');
         s.push_str(name);
 
         for expr in exprs {
-            s.push_str(" ");
+            s.push(' ');
             s.push_str(&walk_expr(self, expr));
         }
 
-        s.push_str(")");
+        s.push(')');
         s
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse;
+
+    #[test]
+    fn conditionals_group_to_the_right_inside_an_assignment() {
+        let statements = parse("x = a ? b, c : d ? e : f;").unwrap();
+        assert_eq!(AstPrinter.print_stmts(&statements), ["(= x (?: a (, b c) (?: d e f)))"]);
+    }
+}
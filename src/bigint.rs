@@ -0,0 +1,413 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// An integer of any size, what integer arithmetic turns into when an i64 would overflow.
+// The magnitude is kept in base 2^32 digits, least significant first, with no zeros on the end,
+// so zero is an empty magnitude and never negative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        Self { negative: false, magnitude: Vec::new() }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        Self::from_magnitude(n < 0, vec![n.unsigned_abs() as u32, (n.unsigned_abs() >> 32) as u32])
+    }
+
+    pub fn from_f64(f: f64) -> Option<Self> {
+        // the integer part of a float, exactly. Floats are mantissa * 2^exponent, so that's a shift.
+        if !f.is_finite() {
+            return None
+        }
+        let f = f.trunc();
+        if f == 0.0 {
+            return Some(Self::zero())
+        }
+        let bits = f.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let magnitude = Self::from_i64(mantissa as i64);
+        let magnitude = if exponent >= 0 {
+            magnitude.shl(exponent as usize)
+        } else {
+            Self::from_i64((mantissa >> -exponent) as i64)
+        };
+        Some(if f < 0.0 { magnitude.neg() } else { magnitude })
+    }
+
+    pub fn parse(digits: &str) -> Option<Self> {
+        // decimal digits with an optional sign
+        match digits.strip_prefix('-') {
+            Some(rest) => Some(Self::parse_radix(rest, 10)?.neg()),
+            None => Self::parse_radix(digits.strip_prefix('+').unwrap_or(digits), 10),
+        }
+    }
+
+    pub fn parse_radix(digits: &str, radix: u32) -> Option<Self> {
+        // unsigned digits in any base up to 36
+        if digits.is_empty() {
+            return None
+        }
+        let mut magnitude = Vec::new();
+        for c in digits.chars() {
+            mul_small(&mut magnitude, radix, c.to_digit(radix)?);
+        }
+        Some(Self::from_magnitude(false, magnitude))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None
+        }
+        let low = self.magnitude.first().copied().unwrap_or_default() as u64;
+        let high = self.magnitude.get(1).copied().unwrap_or_default() as u64;
+        let magnitude = (high << 32) | low;
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // going through the digits gets the rounding right for free
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.magnitude.first().is_some_and(|d| d & 1 == 1)
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.magnitude.last() {
+            Some(top) => self.magnitude.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.magnitude.len() * 4
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::from_magnitude(!self.negative, self.magnitude.clone())
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::from_magnitude(false, self.magnitude.clone())
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::from_magnitude(self.negative, add_magnitudes(&self.magnitude, &other.magnitude))
+        }
+        // different signs, so it's the difference of the magnitudes with the sign of the larger one
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => Self::from_magnitude(other.negative, sub_magnitudes(&other.magnitude, &self.magnitude)),
+            _ => Self::from_magnitude(self.negative, sub_magnitudes(&self.magnitude, &other.magnitude)),
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        let mut product = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let sum = product[i + j] as u64 + a as u64 * b as u64 + carry;
+                product[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            product[i + other.magnitude.len()] = carry as u32;
+        }
+        Self::from_magnitude(self.negative != other.negative, product)
+    }
+
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        // truncating, like i64's / and %, so the remainder takes the sign of self. None for
+        // a division by zero.
+        if other.is_zero() {
+            return None
+        }
+        let (quotient, remainder) = if let [divisor] = other.magnitude.as_slice() {
+            let mut quotient = self.magnitude.clone();
+            let remainder = div_small(&mut quotient, *divisor);
+            (quotient, vec![remainder])
+        } else {
+            div_magnitudes(&self.magnitude, &other.magnitude)
+        };
+        Some((
+            Self::from_magnitude(self.negative != other.negative, quotient),
+            Self::from_magnitude(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> Self {
+        let mut base = self.clone();
+        let mut result = Self::from_i64(1);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+
+    pub fn ten_to(exponent: u32) -> Self {
+        Self::from_i64(10).pow(exponent)
+    }
+
+    pub fn shr(&self, bits: usize) -> Self {
+        // floors like i64's >>, so a negative number that loses any set bits goes one further down
+        let digits = bits / 32;
+        if digits >= self.magnitude.len() {
+            return if self.negative { Self::from_i64(-1) } else { Self::zero() }
+        }
+        let shift = bits % 32;
+        let mut magnitude = self.magnitude[digits..].to_vec();
+        let mut lost = self.magnitude[..digits].iter().any(|&d| d != 0);
+        if shift > 0 {
+            lost |= magnitude[0] & ((1 << shift) - 1) != 0;
+            for i in 0..magnitude.len() {
+                let high = magnitude.get(i + 1).copied().unwrap_or_default();
+                magnitude[i] = (magnitude[i] >> shift) | (high << (32 - shift));
+            }
+        }
+        let shifted = Self::from_magnitude(self.negative, magnitude);
+        if self.negative && lost { shifted.sub(&Self::from_i64(1)) } else { shifted }
+    }
+
+    pub fn bitwise(&self, other: &Self, op: impl Fn(u32, u32) -> u32) -> Self {
+        // `op` digit by digit on the two's complement of both, sign extended to the same width
+        // with a digit to spare so the sign bit of the result is meaningful
+        let width = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let (a, b) = (self.twos_complement(width), other.twos_complement(width));
+        let mut digits: Vec<u32> = a.iter().zip(&b).map(|(&a, &b)| op(a, b)).collect();
+        let negative = digits[width - 1] >> 31 == 1;
+        if negative {
+            negate_digits(&mut digits);
+        }
+        Self::from_magnitude(negative, digits)
+    }
+
+    fn twos_complement(&self, width: usize) -> Vec<u32> {
+        let mut digits = self.magnitude.clone();
+        digits.resize(width, 0);
+        if self.negative {
+            negate_digits(&mut digits);
+        }
+        digits
+    }
+
+    pub fn shl(&self, bits: usize) -> Self {
+        let mut magnitude = vec![0u32; bits / 32];
+        let shift = bits % 32;
+        let mut carry = 0u32;
+        for &digit in &self.magnitude {
+            if shift == 0 {
+                magnitude.push(digit);
+            } else {
+                magnitude.push((digit << shift) | carry);
+                carry = digit >> (32 - shift);
+            }
+        }
+        magnitude.push(carry);
+        Self::from_magnitude(self.negative, magnitude)
+    }
+
+    fn from_magnitude(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        let negative = negative && !magnitude.is_empty();
+        Self { negative, magnitude }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl Eq for BigInt {}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // peel off nine decimal digits at a time, lowest first
+        let mut magnitude = self.magnitude.clone();
+        let mut chunks = Vec::new();
+        while !magnitude.is_empty() {
+            chunks.push(div_small(&mut magnitude, 1_000_000_000));
+            while magnitude.last() == Some(&0) {
+                magnitude.pop();
+            }
+        }
+        let mut s = String::from(if self.negative { "-" } else { "" });
+        match chunks.pop() {
+            Some(top) => s.push_str(&top.to_string()),
+            None => s.push('0'),
+        }
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        write!(f, "{}", s)
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let total = a.get(i).copied().unwrap_or_default() as u64 + b.get(i).copied().unwrap_or_default() as u64 + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    // a - b where a is at least as large as b
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let mut total = digit as i64 - b.get(i).copied().unwrap_or_default() as i64 - borrow;
+        borrow = 0;
+        if total < 0 {
+            total += 1 << 32;
+            borrow = 1;
+        }
+        difference.push(total as u32);
+    }
+    difference
+}
+
+fn mul_small(magnitude: &mut Vec<u32>, factor: u32, addend: u32) {
+    // magnitude = magnitude * factor + addend
+    let mut carry = addend as u64;
+    for digit in magnitude.iter_mut() {
+        let total = *digit as u64 * factor as u64 + carry;
+        *digit = total as u32;
+        carry = total >> 32;
+    }
+    if carry > 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+fn negate_digits(digits: &mut [u32]) {
+    // two's complement negation in place, flip every bit and add one
+    let mut carry = true;
+    for digit in digits.iter_mut() {
+        let (negated, overflow) = (!*digit).overflowing_add(carry as u32);
+        *digit = negated;
+        carry = overflow;
+    }
+}
+
+fn div_small(magnitude: &mut [u32], divisor: u32) -> u32 {
+    // divides in place and returns the remainder
+    let mut remainder = 0u64;
+    for digit in magnitude.iter_mut().rev() {
+        let current = (remainder << 32) | *digit as u64;
+        *digit = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    remainder as u32
+}
+
+fn div_magnitudes(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    // schoolbook long division a bit at a time, slow next to Knuth's algorithm D but short, and
+    // plenty for the size of numbers scripts work with
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        mul_small(&mut remainder, 2, (a[i / 32] >> (i % 32)) & 1);
+        if compare_magnitudes(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitudes(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(digits: &str) -> BigInt {
+        BigInt::parse(digits).unwrap()
+    }
+
+    #[test]
+    fn digits_read_back_the_way_they_were_written() {
+        for digits in ["0", "-1", "18446744073709551616", "-340282366920938463463374607431768211457"] {
+            assert_eq!(big(digits).to_string(), digits);
+        }
+        assert_eq!(BigInt::parse_radix("ff_ff", 16), None);
+        assert_eq!(BigInt::parse_radix("ffff", 16).unwrap().to_string(), "65535");
+    }
+
+    #[test]
+    fn arithmetic_past_the_range_of_an_i64() {
+        let max = BigInt::from_i64(i64::MAX);
+        assert_eq!(max.add(&BigInt::from_i64(1)).to_string(), "9223372036854775808");
+        assert_eq!(max.mul(&max).to_string(), "85070591730234615847396907784232501249");
+        assert_eq!(big("85070591730234615847396907784232501249").sub(&max.mul(&max)), BigInt::zero());
+        assert_eq!(BigInt::from_i64(2).pow(100).to_string(), "1267650600228229401496703205376");
+    }
+
+    #[test]
+    fn division_truncates_like_an_i64s() {
+        let (quotient, remainder) = big("-7").div_rem(&big("2")).unwrap();
+        assert_eq!((quotient.to_string(), remainder.to_string()), ("-3".to_string(), "-1".to_string()));
+        let (quotient, remainder) = big("100000000000000000000").div_rem(&big("30000000000000000000")).unwrap();
+        assert_eq!((quotient.to_string(), remainder.to_string()), ("3".to_string(), "10000000000000000000".to_string()));
+        assert_eq!(big("1").div_rem(&BigInt::zero()), None);
+    }
+
+    #[test]
+    fn only_values_that_fit_convert_back_to_i64() {
+        assert_eq!(BigInt::from_i64(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::expr::{Lambda, Param};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::object::Object;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+// Anything a Lox program can call.
+pub trait LoxCallable {
+    fn name(&self) -> &str;
+    // `paren` is the call's closing ')', for errors to point at. The named arguments come after
+    // the positional ones, each with the name it was given; it's up to the function to match them
+    // up with its parameters and complain about any that don't fit
+    fn call(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>, named: Vec<(Token, Object)>) -> Result<Object, RuntimeError>;
+
+    // functions written in Lox, which a tail call can run without nesting another call
+    fn as_function(&self) -> Option<&LoxFunction> {
+        None
+    }
+}
+
+// functions are only ever the same when they're the very same function
+impl PartialEq for dyn LoxCallable {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other)
+    }
+}
+
+impl fmt::Debug for dyn LoxCallable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+type NativeFn = fn(&Interpreter, &Token, Vec<Object>) -> Result<Object, RuntimeError>;
+
+// A function written in Rust, see natives.rs.
+pub struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        Self { name, arity, function }
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn call(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>, named: Vec<(Token, Object)>) -> Result<Object, RuntimeError> {
+        // natives have no parameter names to match against
+        if let Some((name, _)) = named.first() {
+            return Err(unexpected(name, self.name))
+        }
+        if arguments.len() != self.arity {
+            let message = format!("Expected {} arguments but got {}", self.arity, arguments.len());
+            return Err(RuntimeError::new(paren.clone(), &message))
+        }
+        (self.function)(interpreter, paren, arguments)
+    }
+}
+
+// A call to a Lox function that's ready to run, with its arguments bound to the parameters.
+pub struct Frame {
+    pub name: String,
+    pub body: Rc<Vec<Stmt>>,
+    pub environment: Rc<Environment>,
+}
+
+// A function written in Lox, declared with `fun` or made by a lambda. It keeps the environment it
+// was made in, so the body sees the variables around it for as long as the function lives.
+pub struct LoxFunction {
+    name: String,
+    params: Rc<Vec<Param>>,
+    body: Rc<Vec<Stmt>>,
+    closure: Rc<Environment>,
+}
+
+impl LoxFunction {
+    pub fn new(name: &str, lambda: &Lambda, closure: Rc<Environment>) -> Self {
+        Self { name: name.to_string(), params: Rc::clone(&lambda.params), body: Rc::clone(&lambda.body), closure }
+    }
+
+    pub fn bind(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>, named: Vec<(Token, Object)>) -> Result<Frame, RuntimeError> {
+        // positional arguments fill the parameters in order, what's left over goes to the rest
+        // parameter, then the named ones fill in whichever parameters they name
+        let rest = self.params.last().filter(|p| p.rest);
+        let fixed = &self.params[..self.params.len() - usize::from(rest.is_some())];
+        let mut arguments = arguments.into_iter();
+        let mut values: Vec<Option<Object>> = fixed.iter().map(|_| arguments.next()).collect();
+        let extra: Vec<Object> = arguments.collect();
+        if !extra.is_empty() && rest.is_none() {
+            let at_most = if fixed.iter().any(|p| p.default.is_some()) { "at most " } else { "" };
+            let message = format!("Expected {}{} arguments but got {}", at_most, fixed.len(), fixed.len() + extra.len());
+            return Err(RuntimeError::new(paren.clone(), &message))
+        }
+        for (name, value) in named {
+            let Some(i) = fixed.iter().position(|p| p.name.lexeme == name.lexeme) else {
+                return Err(unexpected(&name, &self.name))
+            };
+            if values[i].is_some() {
+                let message = format!("Argument '{}' given more than once in call to {}", name.lexeme, self.name);
+                return Err(RuntimeError::new(name, &message))
+            }
+            values[i] = Some(value);
+        }
+
+        let missing: Vec<String> = fixed.iter().zip(&values)
+            .filter(|(p, v)| v.is_none() && p.default.is_none())
+            .map(|(p, _)| format!("'{}'", p.name.lexeme))
+            .collect();
+        if !missing.is_empty() {
+            let plural = if missing.len() == 1 { "" } else { "s" };
+            let message = format!("Missing argument{} {} in call to {}", plural, missing.join(", "), self.name);
+            return Err(RuntimeError::new(paren.clone(), &message))
+        }
+
+        // every call gets a fresh environment for its parameters, inside the one it closed over.
+        // Defaults are worked out in it one by one, so they can use the parameters before them
+        let environment = Rc::new(Environment::new(Some(Rc::clone(&self.closure))));
+        for (param, value) in fixed.iter().zip(values) {
+            let value = match (value, &param.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => interpreter.evaluate_in(default, Rc::clone(&environment))?,
+                (None, None) => unreachable!("missing arguments were reported above"),
+            };
+            environment.define(param.name.lexeme.clone(), value);
+        }
+        if let Some(rest) = rest {
+            interpreter.allocate_list(extra.len(), paren)?;
+            environment.define(rest.name.lexeme.clone(), Object::List(Rc::new(extra)));
+        }
+        Ok(Frame { name: self.name.clone(), body: Rc::clone(&self.body), environment })
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn call(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>, named: Vec<(Token, Object)>) -> Result<Object, RuntimeError> {
+        interpreter.call_function(self.bind(interpreter, paren, arguments, named)?)
+    }
+
+    fn as_function(&self) -> Option<&LoxFunction> {
+        Some(self)
+    }
+}
+
+fn unexpected(name: &Token, function: &str) -> RuntimeError {
+    let message = format!("Unexpected argument '{}' in call to {}", name.lexeme, function);
+    RuntimeError::new(name.clone(), &message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn defaults_are_worked_out_at_each_call_that_leaves_them_out() {
+        let source = "\
+var calls = 0;
+fun next() { calls = calls + 1; return calls; }
+fun k(a, b = next(), c = a + b) { print a + b + c; }
+k(1); k(1); k(1, 10); k(b: 5, a: 1); k(1, c: 0);
+";
+        assert_eq!(run(source), Ok("4\n6\n22\n12\n4\n".to_string()));
+    }
+
+    #[test]
+    fn the_rest_parameter_collects_what_is_left_over() {
+        assert_eq!(run("fun r(first, ...rest) { print rest; } r(1); r(1, 2, 3);"), Ok("[]\n[2, 3]\n".to_string()));
+    }
+
+    #[test]
+    fn arity_errors_name_the_arguments_and_the_function() {
+        for (call, message) in [
+            ("fun k(a, b = 2) {} k();", "Missing argument 'a' in call to k"),
+            ("fun k(a, b) {} k();", "Missing arguments 'a', 'b' in call to k"),
+            ("fun k(a, b = 2) {} k(1, c: 3);", "Unexpected argument 'c' in call to k"),
+            ("fun k(a, b = 2) {} k(1, a: 3);", "Argument 'a' given more than once in call to k"),
+            ("fun k(a, b = 2) {} k(1, 2, 3);", "Expected at most 2 arguments but got 3"),
+            ("fun k(a, b) {} k(1, 2, 3);", "Expected 2 arguments but got 3"),
+        ] {
+            assert_eq!(run(call), Err(format!("[line 1] {}", message)));
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+use crate::expr::{Visitor, Assign, Binary, Grouping, Unary, Variable, walk_expr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::interpreter::{Branch, Hook, Interpreter, RuntimeError};
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While, Test, Function, Return};
+use crate::token::Literal;
+use crate::token_type::TokenType;
+
+struct BranchPoint {
+    line: usize,
+    keyword: &'static str, // "if", "and", "or" or "?:"
+    arms: [usize; 2],      // see Hook::branch for which arm is which
+}
+
+// Collects line and branch coverage for `--coverage`. Before the script runs the whole program is
+// walked once so that statements and branches that never execute are still reported, with zero hits.
+// Branch points are told apart by the address of their node, which stays put while the program runs.
+pub struct Coverage {
+    path: String,
+    source: Vec<String>,
+    lines: RefCell<BTreeMap<usize, usize>>, // statement line to times executed
+    branches: RefCell<Vec<BranchPoint>>,    // in source order
+    branch_ids: RefCell<HashMap<usize, usize>>,
+}
+
+impl Hook for Coverage {
+    fn start(&self, _interpreter: &Interpreter, program: &[Stmt]) {
+        for stmt in program {
+            walk_stmt(self, stmt);
+        }
+    }
+
+    fn before_stmt(&self, _interpreter: &Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        *self.lines.borrow_mut().entry(stmt.line()).or_default() += 1;
+        Ok(())
+    }
+
+    fn branch(&self, _interpreter: &Interpreter, branch: Branch, arm: usize) {
+        let key = match branch {
+            Branch::If(e) => e as *const If as usize,
+            Branch::Logical(e) => e as *const Logical as usize,
+            Branch::Conditional(e) => e as *const Conditional as usize,
+        };
+        if let Some(&id) = self.branch_ids.borrow().get(&key) {
+            self.branches.borrow_mut()[id].arms[arm] += 1;
+        }
+    }
+}
+
+impl StmtVisitor<()> for Coverage {
+    fn visit_expression(&self, e: &Expression) {
+        self.statement(e.line);
+        walk_expr(self, &e.expression);
+    }
+
+    fn visit_print(&self, e: &Print) {
+        self.statement(e.line);
+        walk_expr(self, &e.expression);
+    }
+
+    fn visit_var_stmt(&self, e: &StmVariable) {
+        self.statement(e.name.line);
+        walk_expr(self, &e.initializer);
+    }
+
+    fn visit_block_stmt(&self, e: &Block) {
+        self.statement(e.line);
+        for stmt in &e.statements {
+            walk_stmt(self, stmt);
+        }
+    }
+
+    fn visit_if_stmt(&self, e: &If) {
+        self.statement(e.line);
+        walk_expr(self, &e.condition);
+        self.branch_point(e as *const If as usize, e.line, "if");
+        walk_stmt(self, &e.then_branch);
+        if let Some(else_branch) = &e.else_branch {
+            walk_stmt(self, else_branch);
+        }
+    }
+
+    fn visit_while_stmt(&self, e: &While) {
+        self.statement(e.line);
+        walk_expr(self, &e.condition);
+        walk_stmt(self, &e.body);
+    }
+
+    fn visit_test_stmt(&self, e: &Test) {
+        self.statement(e.line);
+        for stmt in &e.body {
+            walk_stmt(self, stmt);
+        }
+    }
+
+    fn visit_function_stmt(&self, e: &Function) {
+        self.statement(e.name.line);
+        self.function(&e.lambda);
+    }
+
+    fn visit_return_stmt(&self, e: &Return) {
+        self.statement(e.keyword.line);
+        if let Some(value) = &e.value {
+            walk_expr(self, value);
+        }
+    }
+}
+
+impl Visitor<()> for Coverage {
+    fn visit_binaryexp(&self, e: &Binary) {
+        walk_expr(self, &e.left);
+        walk_expr(self, &e.right);
+    }
+
+    fn visit_groupingexp(&self, e: &Grouping) {
+        walk_expr(self, &e.expression);
+    }
+
+    fn visit_literalexp(&self, _e: &Literal) {}
+
+    fn visit_unaryexp(&self, e: &Unary) {
+        walk_expr(self, &e.right);
+    }
+
+    fn visit_variableexp(&self, _e: &Variable) {}
+
+    fn visit_assignexp(&self, e: &Assign) {
+        walk_expr(self, &e.value);
+    }
+
+    fn visit_updateexp(&self, e: &Update) {
+        if let Some(value) = &e.value {
+            walk_expr(self, value);
+        }
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) {
+        walk_expr(self, &e.condition);
+        self.branch_point(e as *const Conditional as usize, e.question.line, "?:");
+        walk_expr(self, &e.then_branch);
+        walk_expr(self, &e.else_branch);
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) {
+        walk_expr(self, &e.left);
+        walk_expr(self, &e.right);
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) {
+        self.function(e);
+    }
+
+    fn visit_logicalexp(&self, e: &Logical) {
+        walk_expr(self, &e.left);
+        let keyword = match e.condition.kind {
+            TokenType::And => "and",
+            _ => "or",
+        };
+        self.branch_point(e as *const Logical as usize, e.condition.line, keyword);
+        walk_expr(self, &e.right);
+    }
+
+    fn visit_callexp(&self, e: &Call) {
+        walk_expr(self, &e.callee);
+        for argument in &e.arguments {
+            walk_expr(self, &argument.value);
+        }
+    }
+
+    fn visit_getexp(&self, e: &Get) {
+        walk_expr(self, &e.object);
+    }
+
+    fn visit_indexexp(&self, e: &Index) {
+        walk_expr(self, &e.object);
+        walk_expr(self, &e.index);
+    }
+}
+
+impl Coverage {
+    pub fn new(path: &str, source: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            source: source.lines().map(String::from).collect(),
+            lines: RefCell::new(BTreeMap::new()),
+            branches: RefCell::new(Vec::new()),
+            branch_ids: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn function(&self, lambda: &Lambda) {
+        // defaults can hold branches of their own
+        for default in lambda.params.iter().filter_map(|p| p.default.as_ref()) {
+            walk_expr(self, default);
+        }
+        for stmt in lambda.body.iter() {
+            walk_stmt(self, stmt);
+        }
+    }
+
+    fn statement(&self, line: usize) {
+        self.lines.borrow_mut().entry(line).or_default();
+    }
+
+    fn branch_point(&self, key: usize, line: usize, keyword: &'static str) {
+        let mut branches = self.branches.borrow_mut();
+        self.branch_ids.borrow_mut().insert(key, branches.len());
+        branches.push(BranchPoint { line, keyword, arms: [0, 0] });
+    }
+
+    pub fn lcov(&self) -> String {
+        // the tracefile format genhtml and most coverage services read, see geninfo(1)
+        let lines = self.lines.borrow();
+        let branches = self.branches.borrow();
+        let mut lcov = String::new();
+        writeln!(lcov, "TN:").unwrap();
+        writeln!(lcov, "SF:{}", self.path).unwrap();
+        for (line, hits) in lines.iter() {
+            writeln!(lcov, "DA:{},{}", line, hits).unwrap();
+        }
+        writeln!(lcov, "LF:{}", lines.len()).unwrap();
+        writeln!(lcov, "LH:{}", lines.values().filter(|hits| **hits > 0).count()).unwrap();
+        for (block, branch) in branches.iter().enumerate() {
+            // a branch point that was never reached has its arms reported as `-` rather than 0
+            let reached = branch.arms.iter().any(|taken| *taken > 0);
+            for (arm, taken) in branch.arms.iter().enumerate() {
+                let taken = if reached { taken.to_string() } else { "-".to_string() };
+                writeln!(lcov, "BRDA:{},{},{},{}", branch.line, block, arm, taken).unwrap();
+            }
+        }
+        writeln!(lcov, "BRF:{}", branches.len() * 2).unwrap();
+        writeln!(lcov, "BRH:{}", branches.iter().flat_map(|b| b.arms).filter(|taken| *taken > 0).count()).unwrap();
+        writeln!(lcov, "end_of_record").unwrap();
+        lcov
+    }
+
+    pub fn annotated(&self) -> String {
+        // the source with how often each statement line ran in the margin, `#####` for never,
+        // and how each branch on the line went after it
+        let lines = self.lines.borrow();
+        let branches = self.branches.borrow();
+        let mut report = String::new();
+        for (i, text) in self.source.iter().enumerate() {
+            let line = i + 1;
+            let hits = match lines.get(&line) {
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+                None => "-".to_string(),
+            };
+            write!(report, "{:>9} | {:>4} | {}", hits, line, text).unwrap();
+
+            let notes: Vec<String> = branches.iter().filter(|b| b.line == line).map(|b| {
+                let [first, second] = b.arms;
+                match b.keyword {
+                    "if" => format!("if: then {}, else {}", first, second),
+                    "?:" => format!("?: then {}, else {}", first, second),
+                    keyword => format!("{}: short-circuited {}, evaluated right {}", keyword, first, second),
+                }
+            }).collect();
+            if !notes.is_empty() {
+                write!(report, "    [{}]", notes.join("; ")).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+
+        let (hit, total) = (lines.values().filter(|hits| **hits > 0).count(), lines.len());
+        let arms: Vec<usize> = branches.iter().flat_map(|b| b.arms).collect();
+        let taken = arms.iter().filter(|taken| **taken > 0).count();
+        writeln!(report).unwrap();
+        writeln!(report, "lines: {}/{} ({:.1}%)", hit, total, percent(hit, total)).unwrap();
+        writeln!(report, "branches: {}/{} ({:.1}%)", taken, arms.len(), percent(taken, arms.len())).unwrap();
+        report
+    }
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        return 100.0;
+    }
+    part as f64 * 100.0 / whole as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::testing::parse;
+
+    const SOURCE: &str = "\
+var a = 1;
+if (a > 2) {
+  print \"big\";
+} else {
+  print \"small\";
+}
+print a > 0 or a < 0;
+fun never() {
+  return a;
+}
+";
+
+    fn cover() -> Rc<Coverage> {
+        let coverage = Rc::new(Coverage::new("test.lox", SOURCE));
+        let (mut interpreter, _printed) = Interpreter::capturing();
+        interpreter.add_hook(coverage.clone());
+        assert!(interpreter.try_interpret(&parse(SOURCE).unwrap()).is_ok());
+        coverage
+    }
+
+    #[test]
+    fn lcov_reports_lines_and_branches() {
+        let lcov = cover().lcov();
+        let records: Vec<&str> = lcov.lines().collect();
+        assert_eq!(records, [
+            "TN:", "SF:test.lox",
+            "DA:1,1", "DA:2,1", "DA:3,0", "DA:4,1", "DA:5,1", "DA:7,1", "DA:8,1", "DA:9,0",
+            "LF:8", "LH:6",
+            "BRDA:2,0,0,0", "BRDA:2,0,1,1", "BRDA:7,1,0,1", "BRDA:7,1,1,0",
+            "BRF:4", "BRH:2",
+            "end_of_record",
+        ]);
+    }
+
+    #[test]
+    fn annotated_source_marks_lines_never_run() {
+        let report = cover().annotated();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[1], "        1 |    2 | if (a > 2) {    [if: then 0, else 1]");
+        assert_eq!(lines[2], "    ##### |    3 |   print \"big\";");
+        assert_eq!(lines[5], "        - |    6 | }");
+        assert_eq!(lines[6], "        1 |    7 | print a > 0 or a < 0;    [or: short-circuited 1, evaluated right 0]");
+        assert_eq!(lines[8], "    ##### |    9 |   return a;");
+        assert!(report.ends_with("lines: 6/8 (75.0%)\nbranches: 2/4 (50.0%)\n"));
+    }
+}
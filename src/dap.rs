@@ -0,0 +1,621 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::collect_diagnostics;
+use crate::environment::Environment;
+use crate::interpreter::{CancelHandle, Capabilities, Captured, ErrorKind, Hook, Interpreter, RuntimeError};
+use crate::json::{Json, read_message, write_message};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+const THREAD_ID: usize = 1; // scripts are single threaded, so there's only ever this one
+const SCRIPT: &str = "<script>"; // the bottom frame, the top level of the file
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    Entry,
+    Pause,
+    Step,
+    Next(usize), // pause once we're back at this depth or shallower
+    StepOut(usize), // pause once we're shallower than this depth
+}
+
+// A Lox function that's running, from the caller's point of view.
+struct Call {
+    name: String,
+    line: usize, // where it was called from
+    environment: Rc<Environment>, // the caller's, which it goes back to once the call returns
+}
+
+// A frame of the stack while paused, innermost first in DebugAdapter::frames. Frame ids are
+// positions in that list + 1.
+struct Frame {
+    name: String,
+    line: usize,
+    scopes: Vec<usize>, // its environment chain, innermost first, as positions in `scopes`
+}
+
+// What handling a request means for whoever is waiting on it.
+enum Control {
+    Stay,
+    Resume,
+    Launch(String, bool), // program, stop on entry
+    ConfigurationDone,
+    Disconnect,
+}
+
+// A Debug Adapter Protocol server for editors. Requests are read on their own thread, so breakpoints
+// can change and `pause` works while the script runs; the script itself runs on the calling thread
+// with the adapter hooked into the interpreter, which is where it stops and answers questions about
+// variables and scopes.
+pub struct DebugAdapter<W: Write> {
+    requests: Receiver<Json>,
+    output: RefCell<W>,
+    seq: Cell<usize>,
+    program: RefCell<String>,
+    breakpoints: RefCell<BTreeSet<usize>>,
+    mode: Cell<Mode>,
+    depth: Cell<usize>, // statements currently executing
+    line: Cell<usize>,
+    last_line: Cell<usize>,
+    calls: RefCell<Vec<Call>>, // the Lox functions running, outermost first
+    frames: RefCell<Vec<Frame>>, // the stack while paused
+    scopes: RefCell<Vec<Rc<Environment>>>, // every frame's environments while paused, references are positions + 1
+    disconnected: Cell<bool>,
+    printed: RefCell<Captured>, // what the script printed, sent on as output events
+    cancel: Arc<Mutex<Option<CancelHandle>>>, // the running script's, for the reader thread
+}
+
+impl<W: Write + 'static> Hook for DebugAdapter<W> {
+    fn before_stmt(&self, interpreter: &Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let line = stmt.line();
+        let depth = self.depth.get();
+        self.depth.set(depth + 1);
+        self.line.set(line);
+        self.send_printed();
+
+        // catch up on whatever the client sent while we were running
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => {
+                    if let Control::Disconnect = self.handle(&request, None) {
+                        return Err(self.disconnect(line));
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(self.disconnect(line)),
+            }
+        }
+
+        let arrived = line != self.last_line.replace(line);
+        let reason = match self.mode.get() {
+            Mode::Entry => Some("entry"),
+            Mode::Pause => Some("pause"),
+            Mode::Step => Some("step"),
+            Mode::Next(target) if depth <= target => Some("step"),
+            Mode::StepOut(target) if depth < target => Some("step"),
+            _ if arrived && self.breakpoints.borrow().contains(&line) => Some("breakpoint"),
+            _ => None,
+        };
+        match reason {
+            Some(reason) => self.pause(interpreter, line, reason),
+            None => Ok(()),
+        }
+    }
+
+    fn after_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    fn enter_function(&self, interpreter: &Interpreter, name: &str) {
+        let call = Call { name: name.to_string(), line: self.line.get(), environment: interpreter.environment() };
+        self.calls.borrow_mut().push(call);
+    }
+
+    fn exit_function(&self, _interpreter: &Interpreter) {
+        // back in the caller, on the line of the call. A tail call made on the way out is
+        // entered from here too, so it's shown as called from where its caller was
+        if let Some(call) = self.calls.borrow_mut().pop() {
+            self.line.set(call.line);
+        }
+    }
+}
+
+impl<W: Write + 'static> DebugAdapter<W> {
+    pub fn new(input: impl BufRead + Send + 'static, output: W) -> Self {
+        let (sender, requests) = mpsc::channel();
+        let cancel: Arc<Mutex<Option<CancelHandle>>> = Arc::default();
+        let script = Arc::clone(&cancel);
+        thread::spawn(move || {
+            let mut input = input;
+            while let Ok(Some(body)) = read_message(&mut input) {
+                let Ok(request) = Json::parse(&body) else { continue };
+                // stop the script straight away instead of when it next looks at its requests
+                let command = request.get("command").as_str().unwrap_or_default();
+                if (command == "terminate" || command == "disconnect") && let Some(handle) = &*script.lock().unwrap() {
+                    handle.cancel();
+                }
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            output: RefCell::new(output),
+            seq: Cell::new(1),
+            program: RefCell::new(String::new()),
+            breakpoints: RefCell::new(BTreeSet::new()),
+            mode: Cell::new(Mode::Continue),
+            depth: Cell::new(0),
+            line: Cell::new(0),
+            last_line: Cell::new(0),
+            calls: RefCell::new(Vec::new()),
+            frames: RefCell::new(Vec::new()),
+            scopes: RefCell::new(Vec::new()),
+            disconnected: Cell::new(false),
+            printed: RefCell::new(Captured::default()),
+            cancel,
+        }
+    }
+
+    pub fn serve(self: Rc<Self>) {
+        // wait for the client to tell us what to run and to finish setting breakpoints
+        let (mut program, mut configured) = (None, false);
+        while program.is_none() || !configured {
+            let Ok(request) = self.requests.recv() else { return };
+            match self.handle(&request, None) {
+                Control::Launch(path, stop_on_entry) => {
+                    if stop_on_entry {
+                        self.mode.set(Mode::Entry);
+                    }
+                    program = Some(path);
+                }
+                Control::ConfigurationDone => configured = true,
+                Control::Disconnect => return,
+                _ => {}
+            }
+        }
+        let program = program.unwrap_or_default();
+        self.program.replace(program.clone());
+
+        let exit_code = self.run(&program);
+        if self.disconnected.get() {
+            return;
+        }
+        self.event("exited", Json::object(vec![("exitCode", exit_code.into())]));
+        self.event("terminated", Json::object(vec![]));
+
+        // the client may still have a question or two before it lets go
+        while let Ok(request) = self.requests.recv() {
+            if let Control::Disconnect = self.handle(&request, None) {
+                return;
+            }
+        }
+    }
+
+    fn run(self: &Rc<Self>, program: &str) -> usize {
+        // runs the script with ourselves hooked in, returns the exit code rlox would have used
+        let Ok(source) = fs::read_to_string(program) else {
+            self.output_event("stderr", &format!("Error reading file {}\n", program));
+            return 64;
+        };
+        let (statements, errors) = collect_diagnostics(|| {
+            let tokens = Scanner::new(source).scan_tokens().clone();
+            Parser::new(tokens).parse()
+        });
+        for error in &errors {
+            self.output_event("stderr", &format!("[line {}] {}\n", error.line, error.message));
+        }
+        let Some(statements) = statements.filter(|_| errors.is_empty()) else { return 64 };
+
+        let (mut interpreter, printed) = Interpreter::capturing();
+        self.printed.replace(printed);
+        interpreter.set_stack_size(crate::CALL_STACK_SIZE);
+        interpreter.set_capabilities(Capabilities { io: true });
+        interpreter.add_hook(Rc::clone(self) as Rc<dyn Hook>);
+        self.cancel.lock().unwrap().replace(interpreter.cancel_handle());
+        let result = interpreter.try_interpret(&statements);
+        self.send_printed();
+        match result {
+            Ok(()) => 0,
+            Err(_) if self.disconnected.get() => 0,
+            Err(err) if err.kind() == ErrorKind::Interrupted => 0, // the client asked us to stop
+            Err(err) => {
+                self.output_event("stderr", &format!("{}\n", err));
+                70
+            }
+        }
+    }
+
+    fn pause(&self, interpreter: &Interpreter, line: usize, reason: &str) -> Result<(), RuntimeError> {
+        // every caller is stopped at the line of its call, in the environment it made it from
+        let calls = self.calls.borrow();
+        let names = std::iter::once(SCRIPT).chain(calls.iter().map(|c| c.name.as_str()));
+        let lines = calls.iter().map(|c| c.line).chain(std::iter::once(line));
+        let environments = calls.iter().map(|c| Rc::clone(&c.environment)).chain(std::iter::once(interpreter.environment()));
+
+        let mut scopes: Vec<Rc<Environment>> = Vec::new();
+        let mut frames: Vec<Frame> = names.zip(lines).zip(environments).map(|((name, line), environment)| {
+            // frames share their outer environments, the globals at least, so those are listed once
+            let mut chain = Vec::new();
+            let mut scope = Some(environment);
+            while let Some(environment) = scope {
+                scope = environment.enclosing();
+                chain.push(match scopes.iter().position(|s| Rc::ptr_eq(s, &environment)) {
+                    Some(i) => i,
+                    None => {
+                        scopes.push(environment);
+                        scopes.len() - 1
+                    }
+                });
+            }
+            Frame { name: name.to_string(), line, scopes: chain }
+        }).collect();
+        frames.reverse();
+        drop(calls);
+        self.frames.replace(frames);
+        self.scopes.replace(scopes);
+
+        self.event("stopped", Json::object(vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]));
+        loop {
+            let Ok(request) = self.requests.recv() else { return Err(self.disconnect(line)) };
+            match self.handle(&request, Some(interpreter)) {
+                Control::Resume => return Ok(()),
+                Control::Disconnect => return Err(self.disconnect(line)),
+                _ => {}
+            }
+        }
+    }
+
+    fn disconnect(&self, line: usize) -> RuntimeError {
+        // stops the script by unwinding it like any other runtime error
+        self.disconnected.set(true);
+        RuntimeError::new(Token::default(line), "Debugging session ended")
+    }
+
+    fn handle(&self, request: &Json, paused: Option<&Interpreter>) -> Control {
+        let command = request.get("command").as_str().unwrap_or_default();
+        let arguments = request.get("arguments");
+        let depth = self.depth.get().saturating_sub(1); // the statement we're stopped at
+
+        match command {
+            "initialize" => {
+                self.respond(request, Json::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                ]));
+                self.event("initialized", Json::object(vec![]));
+                Control::Stay
+            }
+            "launch" => {
+                let Some(program) = arguments.get("program").as_str() else {
+                    self.fail(request, "launch needs a 'program' to run");
+                    return Control::Stay;
+                };
+                let stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+                self.respond(request, Json::Null);
+                Control::Launch(program.to_string(), stop_on_entry)
+            }
+            "configurationDone" => {
+                self.respond(request, Json::Null);
+                Control::ConfigurationDone
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = arguments.get("breakpoints").as_array().iter()
+                    .filter_map(|b| b.get("line").as_usize())
+                    .collect();
+                self.breakpoints.replace(lines.iter().copied().collect());
+                let breakpoints = lines.iter().map(|line| Json::object(vec![
+                    ("verified", true.into()),
+                    ("line", (*line).into()),
+                ])).collect();
+                self.respond(request, Json::object(vec![("breakpoints", Json::Array(breakpoints))]));
+                Control::Stay
+            }
+            "threads" => {
+                let thread = Json::object(vec![("id", THREAD_ID.into()), ("name", "main".into())]);
+                self.respond(request, Json::object(vec![("threads", Json::Array(vec![thread]))]));
+                Control::Stay
+            }
+            "pause" => {
+                self.mode.set(Mode::Pause);
+                self.respond(request, Json::Null);
+                Control::Stay
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                self.mode.set(match command {
+                    "continue" => Mode::Continue,
+                    "next" => Mode::Next(depth),
+                    "stepIn" => Mode::Step,
+                    _ => Mode::StepOut(depth),
+                });
+                self.respond(request, Json::object(vec![("allThreadsContinued", true.into())]));
+                Control::Resume
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Json::Null);
+                Control::Disconnect
+            }
+            "stackTrace" | "scopes" | "variables" | "evaluate" => {
+                match paused {
+                    Some(interpreter) => self.inspect(request, interpreter),
+                    None => self.fail(request, "the script is not paused"),
+                }
+                Control::Stay
+            }
+            _ => {
+                self.fail(request, &format!("unsupported request '{}'", command));
+                Control::Stay
+            }
+        }
+    }
+
+    fn inspect(&self, request: &Json, interpreter: &Interpreter) {
+        let arguments = request.get("arguments");
+        match request.get("command").as_str().unwrap_or_default() {
+            "stackTrace" => {
+                let program = self.program.borrow();
+                let name = program.rsplit('/').next().unwrap_or_default();
+                let frames: Vec<Json> = self.frames.borrow().iter().enumerate().map(|(i, frame)| Json::object(vec![
+                    ("id", (i + 1).into()),
+                    ("name", frame.name.as_str().into()),
+                    ("source", Json::object(vec![("name", name.into()), ("path", program.as_str().into())])),
+                    ("line", frame.line.into()),
+                    ("column", 1.into()),
+                ])).collect();
+                let total = frames.len();
+                self.respond(request, Json::object(vec![("stackFrames", Json::Array(frames)), ("totalFrames", total.into())]));
+            }
+            "scopes" => {
+                // every environment in the frame's chain is a scope, the reference is its position in `scopes` + 1
+                let frames = self.frames.borrow();
+                let Some(frame) = self.frame(&frames, arguments) else {
+                    self.fail(request, "no such frame");
+                    return;
+                };
+                let count = frame.scopes.len();
+                let scopes = frame.scopes.iter().enumerate().map(|(i, scope)| {
+                    let name = match i {
+                        _ if i + 1 == count => "Globals",
+                        0 => "Locals",
+                        _ => "Block",
+                    };
+                    Json::object(vec![
+                        ("name", name.into()),
+                        ("variablesReference", (scope + 1).into()),
+                        ("expensive", false.into()),
+                    ])
+                }).collect();
+                self.respond(request, Json::object(vec![("scopes", Json::Array(scopes))]));
+            }
+            "variables" => {
+                let reference = arguments.get("variablesReference").as_usize().unwrap_or_default();
+                let values = match self.scopes.borrow().get(reference.wrapping_sub(1)) {
+                    Some(environment) => environment.values(),
+                    None => Vec::new(),
+                };
+                let variables = values.iter().map(|(name, value)| Json::object(vec![
+                    ("name", name.as_str().into()),
+                    ("value", interpreter.repr(value).into()),
+                    ("variablesReference", 0.into()),
+                ])).collect();
+                self.respond(request, Json::object(vec![("variables", Json::Array(variables))]));
+            }
+            _ => {
+                let source = arguments.get("expression").as_str().unwrap_or_default();
+                let (expr, errors) = collect_diagnostics(|| {
+                    let tokens = Scanner::new(source.to_string()).scan_tokens().clone();
+                    Parser::new(tokens).parse_expression()
+                });
+                // in the selected frame, or where we're stopped if there isn't one
+                let frames = self.frames.borrow();
+                let environment = match self.frame(&frames, arguments) {
+                    Some(frame) => Rc::clone(&self.scopes.borrow()[frame.scopes[0]]),
+                    None => interpreter.environment(),
+                };
+                match expr.filter(|_| errors.is_empty()) {
+                    Some(expr) => match interpreter.evaluate_in(&expr, environment) {
+                        Ok(value) => self.respond(request, Json::object(vec![
+                            ("result", interpreter.repr(&value).into()),
+                            ("variablesReference", 0.into()),
+                        ])),
+                        Err(err) => self.fail(request, &err.to_string()),
+                    },
+                    None => {
+                        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+                        self.fail(request, &messages.join("\n"));
+                    }
+                }
+            }
+        }
+    }
+
+    fn frame<'a>(&self, frames: &'a [Frame], arguments: &Json) -> Option<&'a Frame> {
+        // the innermost frame unless the request names one
+        match arguments.get("frameId").as_usize() {
+            Some(id) => frames.get(id.wrapping_sub(1)),
+            None => frames.first(),
+        }
+    }
+
+    fn respond(&self, request: &Json, body: Json) {
+        self.send(vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("success", true.into()),
+            ("command", request.get("command").clone()),
+            ("body", body),
+        ]);
+    }
+
+    fn fail(&self, request: &Json, message: &str) {
+        self.send(vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("success", false.into()),
+            ("command", request.get("command").clone()),
+            ("message", message.into()),
+        ]);
+    }
+
+    fn event(&self, event: &str, body: Json) {
+        self.send(vec![("type", "event".into()), ("event", event.into()), ("body", body)]);
+    }
+
+    fn send_printed(&self) {
+        let printed = self.printed.borrow().take();
+        if !printed.is_empty() {
+            self.output_event("stdout", &printed);
+        }
+    }
+
+    fn output_event(&self, category: &str, output: &str) {
+        self.event("output", Json::object(vec![("category", category.into()), ("output", output.into())]));
+    }
+
+    fn send(&self, mut fields: Vec<(&str, Json)>) {
+        let seq = self.seq.replace(self.seq.get() + 1);
+        fields.insert(0, ("seq", seq.into()));
+        // if the client is gone the reader thread notices and we wind down from there
+        let _ = write_message(&mut *self.output.borrow_mut(), &Json::object(fields));
+    }
+}
+
+pub fn listen(port: u16) -> io::Result<()> {
+    // serves a single client over TCP, which keeps the script's own stdout out of the protocol
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Debug adapter listening on 127.0.0.1:{}", listener.local_addr()?.port());
+    let (stream, _) = listener.accept()?;
+    let input = io::BufReader::new(stream.try_clone()?);
+    Rc::new(DebugAdapter::new(input, stream)).serve();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, PipeReader, PipeWriter};
+
+    const SOURCE: &str = "\
+fun inner(x) {
+  var y = x * 2;
+  print y;
+}
+fun outer(a) {
+  inner(a + 1);
+}
+outer(1);
+";
+
+    // the editor's end of a session with an adapter serving on its own thread
+    struct Client {
+        requests: PipeWriter,
+        messages: BufReader<PipeReader>,
+        seq: usize,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (input, requests) = io::pipe().unwrap();
+            let (messages, output) = io::pipe().unwrap();
+            thread::spawn(move || Rc::new(DebugAdapter::new(BufReader::new(input), output)).serve());
+            Self { requests, messages: BufReader::new(messages), seq: 0 }
+        }
+
+        fn request(&mut self, command: &str, arguments: Json) -> Json {
+            // the body of its response, passing over any events sent first
+            self.seq += 1;
+            let request = Json::object(vec![
+                ("seq", self.seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments),
+            ]);
+            write_message(&mut self.requests, &request).unwrap();
+            loop {
+                let message = self.receive();
+                if message.get("type").as_str() == Some("response") {
+                    assert_eq!(message.get("request_seq").as_usize(), Some(self.seq));
+                    assert_eq!(message.get("success"), &Json::Bool(true), "{} failed: {}", command, message);
+                    return message.get("body").clone()
+                }
+            }
+        }
+
+        fn event(&mut self, event: &str) -> Json {
+            loop {
+                let message = self.receive();
+                if message.get("event").as_str() == Some(event) {
+                    return message.get("body").clone()
+                }
+            }
+        }
+
+        fn receive(&mut self) -> Json {
+            let body = read_message(&mut self.messages).unwrap().expect("the adapter hung up");
+            Json::parse(&body).unwrap()
+        }
+    }
+
+    #[test]
+    fn a_session_stops_at_a_breakpoint_and_shows_each_frame() {
+        let path = std::env::temp_dir().join(format!("rlox-dap-{}.lox", std::process::id()));
+        fs::write(&path, SOURCE).unwrap();
+        let program = path.to_str().unwrap();
+
+        let mut client = Client::start();
+        let capabilities = client.request("initialize", Json::object(vec![("adapterID", "rlox".into())]));
+        assert_eq!(capabilities.get("supportsConfigurationDoneRequest"), &Json::Bool(true));
+        client.event("initialized");
+        let breakpoints = client.request("setBreakpoints", Json::object(vec![
+            ("source", Json::object(vec![("path", program.into())])),
+            ("breakpoints", Json::Array(vec![Json::object(vec![("line", 3.into())])])),
+        ]));
+        assert_eq!(breakpoints.get("breakpoints").as_array()[0].get("verified"), &Json::Bool(true));
+        client.request("launch", Json::object(vec![("program", program.into())]));
+        client.request("configurationDone", Json::Null);
+
+        let stopped = client.event("stopped");
+        assert_eq!(stopped.get("reason").as_str(), Some("breakpoint"));
+
+        let trace = client.request("stackTrace", Json::object(vec![("threadId", THREAD_ID.into())]));
+        let frames: Vec<(&str, usize)> = trace.get("stackFrames").as_array().iter()
+            .map(|f| (f.get("name").as_str().unwrap(), f.get("line").as_usize().unwrap()))
+            .collect();
+        assert_eq!(frames, [("inner", 3), ("outer", 6), ("<script>", 8)]);
+
+        // the caller's own locals, not the innermost frame's
+        let outer = trace.get("stackFrames").as_array()[1].get("id").clone();
+        let scopes = client.request("scopes", Json::object(vec![("frameId", outer)]));
+        let locals = scopes.get("scopes").as_array()[0].get("variablesReference").clone();
+        let variables = client.request("variables", Json::object(vec![("variablesReference", locals)]));
+        assert_eq!(variables.get("variables").as_array(), [Json::object(vec![
+            ("name", "a".into()),
+            ("value", "1".into()),
+            ("variablesReference", 0.into()),
+        ])]);
+
+        client.request("continue", Json::object(vec![("threadId", THREAD_ID.into())]));
+        assert_eq!(client.event("output").get("output").as_str(), Some("4\n"));
+        assert_eq!(client.event("exited").get("exitCode").as_usize(), Some(0));
+        client.event("terminated");
+        client.request("disconnect", Json::Null);
+        fs::remove_file(&path).unwrap();
+    }
+}
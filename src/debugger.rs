@@ -0,0 +1,255 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::collect_diagnostics;
+use crate::interpreter::{Hook, Interpreter, RuntimeError};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+const HELP: &str = "\
+step, s            run until the next statement
+next, n            run until the next statement in this block, stepping over nested ones
+continue, c        run until a breakpoint
+break, b <line>    pause whenever <line> is reached
+clear <line>       remove the breakpoint on <line>
+print, p <expr>    evaluate <expr> in the current scope
+locals             show the variables of every scope, innermost first
+backtrace, bt      show the statements being executed and their functions, innermost first
+quit, q            stop the script";
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Continue,
+    Step,
+    Next(usize), // pause once we're back at this depth or shallower
+}
+
+// A terminal debugger for `--debug`. It's a hook that runs before every statement and, when a
+// breakpoint is hit or we're stepping, reads commands from stdin until told to carry on.
+pub struct Debugger {
+    source: Vec<String>,
+    input: RefCell<Box<dyn BufRead>>,
+    output: RefCell<Box<dyn Write>>, // our prompts and answers, the script prints where it always does
+    breakpoints: RefCell<BTreeSet<usize>>,
+    mode: Cell<Mode>,
+    stack: RefCell<Vec<(usize, String, usize)>>, // the statements currently running: line, source text and call
+    calls: RefCell<Vec<String>>, // the Lox functions running, outermost first
+    last_line: Cell<usize>,
+}
+
+impl Hook for Debugger {
+    fn before_stmt(&self, interpreter: &Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let line = stmt.line();
+        let depth = self.stack.borrow().len();
+        let call = self.calls.borrow().len();
+        self.stack.borrow_mut().push((line, self.source_line(line), call));
+
+        // a breakpoint fires when execution arrives on its line, not for every statement on it
+        let arrived = line != self.last_line.replace(line);
+        let pause = match self.mode.get() {
+            Mode::Step => true,
+            Mode::Next(target) => depth <= target,
+            Mode::Continue => false,
+        };
+        if pause || (arrived && self.breakpoints.borrow().contains(&line)) {
+            return self.prompt(interpreter, line, depth);
+        }
+        Ok(())
+    }
+
+    fn after_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) {
+        self.stack.borrow_mut().pop();
+    }
+
+    fn enter_function(&self, _interpreter: &Interpreter, name: &str) {
+        self.calls.borrow_mut().push(name.to_string());
+    }
+
+    fn exit_function(&self, _interpreter: &Interpreter) {
+        self.calls.borrow_mut().pop();
+    }
+}
+
+impl Debugger {
+    pub fn new(source: &str) -> Self {
+        Self::with_io(source, Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+
+    pub fn with_io(source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            source: source.lines().map(String::from).collect(),
+            input: RefCell::new(input),
+            output: RefCell::new(output),
+            breakpoints: RefCell::new(BTreeSet::new()),
+            mode: Cell::new(Mode::Step), // pause before the first statement to let breakpoints be set
+            stack: RefCell::new(Vec::new()),
+            calls: RefCell::new(Vec::new()),
+            last_line: Cell::new(0),
+        }
+    }
+
+    fn source_line(&self, line: usize) -> String {
+        self.source.get(line.wrapping_sub(1)).map(|l| l.trim().to_string()).unwrap_or_default()
+    }
+
+    fn prompt(&self, interpreter: &Interpreter, line: usize, depth: usize) -> Result<(), RuntimeError> {
+        self.say(&format!("{:>4} | {}", line, self.source_line(line)));
+        loop {
+            let _ = write!(self.output.borrow_mut(), "(debug) ");
+            let _ = self.output.borrow_mut().flush();
+
+            let mut input = String::new();
+            if self.input.borrow_mut().read_line(&mut input).unwrap_or(0) == 0 {
+                // nobody left to talk to, let the script finish
+                self.mode.set(Mode::Continue);
+                return Ok(());
+            }
+            let input = input.trim();
+            let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+            let argument = argument.trim();
+
+            match command {
+                "step" | "s" => {
+                    self.mode.set(Mode::Step);
+                    return Ok(());
+                }
+                "next" | "n" => {
+                    self.mode.set(Mode::Next(depth));
+                    return Ok(());
+                }
+                "continue" | "c" => {
+                    self.mode.set(Mode::Continue);
+                    return Ok(());
+                }
+                "break" | "b" | "clear" => match argument.parse::<usize>() {
+                    Ok(line) if command == "clear" => {
+                        self.breakpoints.borrow_mut().remove(&line);
+                    }
+                    Ok(line) => {
+                        self.breakpoints.borrow_mut().insert(line);
+                        self.say(&format!("breakpoint on line {}: {}", line, self.source_line(line)));
+                    }
+                    Err(_) => self.say(&format!("{} needs a line number", command)),
+                },
+                "print" | "p" => self.print(interpreter, argument),
+                "locals" => self.locals(interpreter),
+                "backtrace" | "bt" => {
+                    // a block usually starts on the line of its `if`/`while`, show those once per call
+                    let mut stack = self.stack.borrow().clone();
+                    stack.dedup_by_key(|(line, _, call)| (*line, *call));
+                    let calls = self.calls.borrow();
+                    for (i, (line, text, call)) in stack.iter().rev().enumerate() {
+                        let function = match call {
+                            0 => "<script>",
+                            _ => &calls[call - 1],
+                        };
+                        self.say(&format!("#{} line {} in {}: {}", i, line, function, text));
+                    }
+                }
+                "quit" | "q" => {
+                    return Err(RuntimeError::new(Token::default(line), "Stopped by the debugger"));
+                }
+                "" => {}
+                "help" | "h" => self.say(HELP),
+                _ => self.say(&format!("unknown command '{}', try 'help'", command)),
+            }
+        }
+    }
+
+    fn say(&self, text: &str) {
+        let _ = writeln!(self.output.borrow_mut(), "{}", text);
+    }
+
+    fn print(&self, interpreter: &Interpreter, source: &str) {
+        let (expr, errors) = collect_diagnostics(|| {
+            let tokens = Scanner::new(source.to_string()).scan_tokens().clone();
+            Parser::new(tokens).parse_expression()
+        });
+        for error in errors {
+            self.say(&error.message);
+        }
+        if let Some(expr) = expr {
+            match interpreter.evaluate(&expr) {
+                Ok(value) => self.say(&interpreter.stringify(&value)),
+                Err(err) => self.say(&err.to_string()),
+            }
+        }
+    }
+
+    fn locals(&self, interpreter: &Interpreter) {
+        let mut scope = Some(interpreter.environment());
+        while let Some(environment) = scope {
+            let values = environment.values();
+            let global = environment.enclosing().is_none();
+            if global || !values.is_empty() {
+                self.say(&format!("{}:", if global { "globals" } else { "block" }));
+            }
+            for (name, value) in values {
+                self.say(&format!("    {} = {}", name, interpreter.stringify(&value)));
+            }
+            scope = environment.enclosing();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::interpreter::Captured;
+    use crate::testing::parse;
+
+    const SOURCE: &str = "\
+fun inner(x) {
+  var y = x * 2;
+  print y;
+}
+inner(1);
+";
+
+    fn debug(commands: &str) -> (Result<(), String>, String) {
+        // how the script ended and what the debugger said along the way
+        let said = Captured::default();
+        let debugger = Debugger::with_io(SOURCE, Box::new(io::Cursor::new(commands.to_string())), Box::new(said.clone()));
+        let (mut interpreter, _printed) = Interpreter::capturing();
+        interpreter.add_hook(Rc::new(debugger));
+        let result = interpreter.try_interpret(&parse(SOURCE).unwrap()).map_err(|err| err.to_string());
+        (result, said.take())
+    }
+
+    #[test]
+    fn a_breakpoint_pauses_with_a_backtrace_of_the_calls() {
+        let (result, said) = debug("b 3\nc\nbt\np y + 1\nc\n");
+        assert!(result.is_ok());
+        assert_eq!(said, "   1 | fun inner(x) {
+(debug) breakpoint on line 3: print y;
+(debug)    3 | print y;
+(debug) #0 line 3 in inner: print y;
+#1 line 5 in <script>: inner(1);
+(debug) 3
+(debug) ");
+    }
+
+    #[test]
+    fn locals_walks_the_scopes_from_the_innermost() {
+        let (_, said) = debug("b 3\nc\nlocals\nc\n");
+        assert!(said.contains("(debug) block:\n    x = 1\n    y = 2\nglobals:\n"));
+        assert!(said.contains("    inner = <fn inner>\n"));
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (result, said) = debug("n\nn\n");
+        assert!(result.is_ok());
+        assert_eq!(said, "   1 | fun inner(x) {\n(debug)    5 | inner(1);\n(debug) ");
+    }
+
+    #[test]
+    fn quit_stops_the_script() {
+        let (result, _) = debug("s\nq\n");
+        assert_eq!(result, Err("[line 5] Stopped by the debugger".to_string()));
+    }
+}
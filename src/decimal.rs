@@ -0,0 +1,308 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bigint::BigInt;
+
+// How many places a quotient is worked out to, 1.0d / 3 is 0.333... with this many 3s
+pub const DIVISION_PLACES: u32 = 28;
+
+// An exact decimal number, `unscaled / 10^scale`. The scale is kept as written, so 1.10d prints
+// as 1.10 and still equals 1.1d.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+    unscaled: BigInt,
+    scale: u32,
+}
+
+// What to do with the digits past the last place kept, when dividing and rounding decimals.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Rounding {
+    #[default]
+    HalfEven, // to the nearest, ties to the even neighbour, the banker's rounding
+    HalfUp,   // to the nearest, ties away from zero
+    HalfDown, // to the nearest, ties towards zero
+    Up,       // away from zero
+    Down,     // towards zero
+    Ceiling,  // towards positive infinity
+    Floor,    // towards negative infinity
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-down" => Ok(Rounding::HalfDown),
+            "up" => Ok(Rounding::Up),
+            "down" => Ok(Rounding::Down),
+            "ceiling" => Ok(Rounding::Ceiling),
+            "floor" => Ok(Rounding::Floor),
+            _ => Err(format!(
+                "unknown rounding '{}', expected half-even, half-up, half-down, up, down, ceiling or floor", s
+            )),
+        }
+    }
+}
+
+impl Decimal {
+    pub fn from_integer(n: BigInt) -> Self {
+        Self { unscaled: n, scale: 0 }
+    }
+
+    pub fn from_f64(f: f64) -> Option<Self> {
+        // the shortest decimal that reads back as the same float, so 0.1 becomes 0.1d rather
+        // than the 55 digits of the double nearest to it
+        if !f.is_finite() {
+            return None
+        }
+        Self::parse(&f.to_string())
+    }
+
+    pub fn from_f64_exact(f: f64) -> Option<Self> {
+        // every digit of the float's actual value. A float is mantissa * 2^exponent, and with a
+        // negative exponent that's mantissa * 5^-exponent / 10^-exponent.
+        if !f.is_finite() {
+            return None
+        }
+        let bits = f.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = (bits & ((1 << 52) - 1)) as i64;
+        let (mantissa, exponent) = match biased {
+            0 => (fraction, -1074), // subnormal, there's no implicit leading 1
+            _ => (fraction | (1 << 52), biased - 1075),
+        };
+        let mantissa = BigInt::from_i64(if f < 0.0 { -mantissa } else { mantissa });
+        if exponent >= 0 {
+            return Some(Self::from_integer(mantissa.mul(&BigInt::from_i64(2).pow(exponent as u32))))
+        }
+        let scale = -exponent as u32;
+        Some(Self { unscaled: mantissa.mul(&BigInt::from_i64(5).pow(scale)), scale })
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        // digits with an optional sign and '.'
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        if fraction.starts_with(['+', '-']) {
+            return None
+        }
+        let unscaled = BigInt::parse(&format!("{}{}", whole, fraction))?;
+        Some(Self { unscaled, scale: fraction.len() as u32 })
+    }
+
+    pub fn times_ten_to(&self, exponent: i32) -> Self {
+        // self * 10^exponent, exactly, for literals like 1.5e3d
+        let scale = self.scale as i64 - exponent as i64;
+        if scale >= 0 {
+            return Self { unscaled: self.unscaled.clone(), scale: scale as u32 }
+        }
+        Self { unscaled: self.unscaled.mul(&BigInt::ten_to(-scale as u32)), scale: 0 }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn trunc(&self) -> BigInt {
+        // the integer part, dropping the fraction towards zero
+        let (quotient, _) = self.unscaled.div_rem(&BigInt::ten_to(self.scale)).expect("10^scale is never 0");
+        quotient
+    }
+
+    pub fn places(&self) -> u32 {
+        // how many digits it has after the point
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.unscaled.is_zero()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.unscaled.bytes()
+    }
+
+    pub fn neg(&self) -> Self {
+        Self { unscaled: self.unscaled.neg(), scale: self.scale }
+    }
+
+    pub fn abs(&self) -> Self {
+        Self { unscaled: self.unscaled.abs(), scale: self.scale }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let (l, r, scale) = self.align(other);
+        Self { unscaled: l.add(&r), scale }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self { unscaled: self.unscaled.mul(&other.unscaled), scale: self.scale + other.scale }
+    }
+
+    pub fn div(&self, other: &Self, rounding: Rounding) -> Option<Self> {
+        // worked out to DIVISION_PLACES, then the zeros past the places of the operands dropped
+        // again, so 1.00d / 4 is 0.25 and 1.00d / 2 is 0.50
+        if other.is_zero() {
+            return None
+        }
+        let places = DIVISION_PLACES.max(self.scale.saturating_sub(other.scale));
+        let numerator = self.unscaled.mul(&BigInt::ten_to(places + other.scale - self.scale));
+        let (quotient, remainder) = numerator.div_rem(&other.unscaled)?;
+        let quotient = Self { unscaled: round(quotient, &remainder, &other.unscaled, rounding), scale: places };
+        Some(quotient.trim(self.scale.max(other.scale)))
+    }
+
+    pub fn div_rem(&self, other: &Self) -> Option<(BigInt, Self)> {
+        // the truncated integer quotient and what's left over, like `~/` and `%` on integers
+        let (l, r, scale) = self.align(other);
+        let (quotient, remainder) = l.div_rem(&r)?;
+        Some((quotient, Self { unscaled: remainder, scale }))
+    }
+
+    pub fn pow(&self, exponent: u32) -> Option<Self> {
+        // None when the result would have more places than a scale can count
+        let scale = self.scale.checked_mul(exponent)?;
+        Some(Self { unscaled: self.unscaled.pow(exponent), scale })
+    }
+
+    pub fn round(&self, places: u32, rounding: Rounding) -> Self {
+        // to exactly `places` places, padding with zeros when there are fewer
+        if places >= self.scale {
+            let unscaled = self.unscaled.mul(&BigInt::ten_to(places - self.scale));
+            return Self { unscaled, scale: places }
+        }
+        let divisor = BigInt::ten_to(self.scale - places);
+        let (quotient, remainder) = self.unscaled.div_rem(&divisor).expect("10^n is never 0");
+        Self { unscaled: round(quotient, &remainder, &divisor, rounding), scale: places }
+    }
+
+    fn trim(mut self, keep: u32) -> Self {
+        // drops zeros from the end of the fraction, leaving at least `keep` places
+        let ten = BigInt::from_i64(10);
+        while self.scale > keep {
+            let (quotient, remainder) = self.unscaled.div_rem(&ten).expect("10 is not 0");
+            if !remainder.is_zero() {
+                break
+            }
+            self = Self { unscaled: quotient, scale: self.scale - 1 };
+        }
+        self
+    }
+
+    fn align(&self, other: &Self) -> (BigInt, BigInt, u32) {
+        // both unscaled values brought to the larger of the two scales
+        let scale = self.scale.max(other.scale);
+        let l = self.unscaled.mul(&BigInt::ten_to(scale - self.scale));
+        let r = other.unscaled.mul(&BigInt::ten_to(scale - other.scale));
+        (l, r, scale)
+    }
+}
+
+fn round(quotient: BigInt, remainder: &BigInt, divisor: &BigInt, rounding: Rounding) -> BigInt {
+    // quotient is a truncated division that left `remainder`, decides whether it should have
+    // gone one further from zero instead
+    if remainder.is_zero() {
+        return quotient
+    }
+    let negative = remainder.is_negative() != divisor.is_negative();
+    let half = remainder.abs().mul(&BigInt::from_i64(2)).cmp(&divisor.abs());
+    let away = match rounding {
+        Rounding::Up => true,
+        Rounding::Down => false,
+        Rounding::Ceiling => !negative,
+        Rounding::Floor => negative,
+        Rounding::HalfUp => half != Ordering::Less,
+        Rounding::HalfDown => half == Ordering::Greater,
+        Rounding::HalfEven => half == Ordering::Greater || (half == Ordering::Equal && quotient.is_odd()),
+    };
+    if !away {
+        return quotient
+    }
+    quotient.add(&BigInt::from_i64(if negative { -1 } else { 1 }))
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (l, r, _) = self.align(other);
+        Some(l.cmp(&r))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.unscaled.abs().to_string();
+        let sign = if self.unscaled.is_negative() { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits)
+        }
+        // padded by hand, a format width can't go past u16::MAX and a scale can
+        let digits = "0".repeat((scale + 1).saturating_sub(digits.len())) + &digits;
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn decimals_keep_the_places_they_were_written_with() {
+        assert_eq!(decimal("1.10").to_string(), "1.10");
+        assert_eq!(decimal("-0.05").to_string(), "-0.05");
+        assert_eq!(decimal("2.5").times_ten_to(3).to_string(), "2500");
+    }
+
+    #[test]
+    fn any_number_of_places_prints() {
+        // more than a format width can pad to
+        let tiny = decimal("1").times_ten_to(-70_000).to_string();
+        assert_eq!(tiny.len(), 70_002);
+        assert!(tiny.starts_with("0.000") && tiny.ends_with("001"));
+    }
+
+    #[test]
+    fn sums_and_products_are_exact() {
+        assert_eq!(decimal("0.1").add(&decimal("0.2")).to_string(), "0.3");
+        assert_eq!(decimal("1.10").mul(&decimal("3")).to_string(), "3.30");
+        assert_eq!(decimal("1.5").sub(&decimal("2.25")).to_string(), "-0.75");
+    }
+
+    #[test]
+    fn division_rounds_the_way_it_is_told_to() {
+        assert_eq!(decimal("1").div(&decimal("3"), Rounding::HalfEven).unwrap().to_string(), "0.3333333333333333333333333333");
+        assert_eq!(decimal("2").div(&decimal("3"), Rounding::Down).unwrap().to_string(), "0.6666666666666666666666666666");
+        assert_eq!(decimal("1.00").div(&decimal("4"), Rounding::HalfEven).unwrap().to_string(), "0.25");
+        assert_eq!(decimal("1").div(&decimal("0"), Rounding::HalfEven), None);
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            (Rounding::HalfEven, ["2", "4", "-2"]),
+            (Rounding::HalfUp, ["3", "4", "-3"]),
+            (Rounding::HalfDown, ["2", "3", "-2"]),
+            (Rounding::Up, ["3", "4", "-3"]),
+            (Rounding::Down, ["2", "3", "-2"]),
+            (Rounding::Ceiling, ["3", "4", "-2"]),
+            (Rounding::Floor, ["2", "3", "-3"]),
+        ];
+        for (rounding, expected) in cases {
+            let rounded = ["2.5", "3.5", "-2.5"].map(|d| decimal(d).round(0, rounding).to_string());
+            assert_eq!(rounded, expected, "{:?}", rounding);
+        }
+        assert_eq!(decimal("2.345").round(2, Rounding::HalfEven).to_string(), "2.34");
+        assert_eq!(decimal("2.3").round(3, Rounding::HalfEven).to_string(), "2.300");
+    }
+}
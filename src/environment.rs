@@ -17,7 +17,7 @@ impl Environment {
     pub fn new(enclosing: Option<Rc<Environment>>) -> Self {
         Environment {
             variables: RefCell::new(HashMap::new()),
-            enclosing: enclosing,
+            enclosing,
         }
     }
 
@@ -41,6 +41,19 @@ impl Environment {
         ))
     }
 
+    pub fn enclosing(&self) -> Option<Rc<Environment>> {
+        self.enclosing.clone()
+    }
+
+    pub fn values(&self) -> Vec<(String, Object)> {
+        // everything defined in this scope alone, sorted so listings are stable
+        let mut values: Vec<(String, Object)> = self.variables.borrow().iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        values
+    }
+
     pub fn assign(&self, name: &Token, obj: Object) -> Result<(), RuntimeError>{
         if self.variables.borrow().contains_key(&name.lexeme) {
             self.define(name.lexeme.clone(), obj);
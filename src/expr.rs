@@ -1,5 +1,9 @@
+use std::rc::Rc;
+
+use crate::stmt::Stmt;
 use crate::token::{Token, Literal};
 
+#[derive(Clone)]
 pub enum Expr {
     Literal(Literal),
     Grouping(Grouping),
@@ -7,39 +11,124 @@ pub enum Expr {
     Binary(Binary),
     Variable(Variable),
     Assign(Assign),
-    Logical(Logical)
+    Update(Update),
+    Logical(Logical),
+    Conditional(Conditional),
+    Sequence(Sequence),
+    Lambda(Lambda),
+    Call(Call),
+    Get(Get),
+    Index(Index),
 }
 
+#[derive(Clone)]
 pub struct Grouping {
     pub expression: Box<Expr>,
 }
 
+#[derive(Clone)]
 pub struct Unary {
     pub op: Token,
     pub right: Box<Expr>,
 }
 
+#[derive(Clone)]
 pub struct Binary {
     pub left: Box<Expr>,
     pub op: Token,
     pub right: Box<Expr>,
 }
 
+#[derive(Clone)]
 pub struct Variable {
     pub name: Token,
 }
 
+#[derive(Clone)]
 pub struct Assign {
     pub name: Token,
    pub value: Box<Expr>,
 }
 
+// `name += value` and the other compound assignments, and `++`/`--` before or after the name,
+// which have no value and add or take away 1
+#[derive(Clone)]
+pub struct Update {
+    pub name: Token,
+    pub op: Token,
+    pub value: Option<Box<Expr>>,
+    pub prefix: bool, // `++a` is the new value, `a++` the old one
+}
+
+#[derive(Clone)]
 pub struct Logical {
     pub left: Box<Expr>,
     pub condition: Token,
     pub right: Box<Expr>
 }
 
+// `condition ? then_branch : else_branch`
+#[derive(Clone)]
+pub struct Conditional {
+    pub condition: Box<Expr>,
+    pub question: Token,
+    pub then_branch: Box<Expr>,
+    pub else_branch: Box<Expr>,
+}
+
+// `left, right`, both evaluated in order and the value is the right one's
+#[derive(Clone)]
+pub struct Sequence {
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+}
+
+// `fun (params) { body }`, and what a `fun` declaration declares. `(params) => value` is one too,
+// with `arrow` set and a body that's just `return value;`.
+#[derive(Clone)]
+pub struct Lambda {
+    pub params: Rc<Vec<Param>>, // shared like the body, defaults are evaluated on every call
+    pub body: Rc<Vec<Stmt>>, // shared with every function value made from it
+    pub arrow: bool,
+    pub end_line: usize, // line of the closing '}'
+}
+
+// `name`, `name = default` or `...name`, which collects the arguments left over into a list
+pub struct Param {
+    pub name: Token,
+    pub default: Option<Expr>,
+    pub rest: bool,
+}
+
+#[derive(Clone)]
+pub struct Call {
+    pub callee: Box<Expr>,
+    pub paren: Token, // the closing ')', runtime errors in the call are reported on its line
+    pub arguments: Vec<Argument>,
+}
+
+// `value`, or `name: value` for a named argument
+#[derive(Clone)]
+pub struct Argument {
+    pub name: Option<Token>,
+    pub value: Expr,
+}
+
+// `object.name`, only modules have properties so far
+#[derive(Clone)]
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: Token,
+}
+
+// `object[index]`
+#[derive(Clone)]
+pub struct Index {
+    pub object: Box<Expr>,
+    pub bracket: Token, // the closing ']', for errors to point at
+    pub index: Box<Expr>,
+}
+
 pub trait Visitor<T> {
     fn visit_binaryexp(&self, e: &Binary) -> T;
     fn visit_groupingexp(&self, e: &Grouping) -> T;
@@ -47,7 +136,14 @@ pub trait Visitor<T> {
     fn visit_unaryexp(&self, e: &Unary) -> T;
     fn visit_variableexp(&self, e: &Variable) -> T;
     fn visit_assignexp(&self, e: &Assign) -> T;
+    fn visit_updateexp(&self, e: &Update) -> T;
     fn visit_logicalexp(&self, e: &Logical) -> T;
+    fn visit_conditionalexp(&self, e: &Conditional) -> T;
+    fn visit_sequenceexp(&self, e: &Sequence) -> T;
+    fn visit_lambdaexp(&self, e: &Lambda) -> T;
+    fn visit_callexp(&self, e: &Call) -> T;
+    fn visit_getexp(&self, e: &Get) -> T;
+    fn visit_indexexp(&self, e: &Index) -> T;
 }
 
 pub fn walk_expr<T>(visitor: &dyn Visitor<T>, e: &Expr) -> T {
@@ -58,6 +154,13 @@ pub fn walk_expr<T>(visitor: &dyn Visitor<T>, e: &Expr) -> T {
         Expr::Grouping(grouping) => visitor.visit_groupingexp(grouping),
         Expr::Variable(variable) => visitor.visit_variableexp(variable),
         Expr::Assign(assign) => visitor.visit_assignexp(assign),
-        Expr::Logical(logical) => visitor.visit_logicalexp(logical)
+        Expr::Update(update) => visitor.visit_updateexp(update),
+        Expr::Logical(logical) => visitor.visit_logicalexp(logical),
+        Expr::Conditional(conditional) => visitor.visit_conditionalexp(conditional),
+        Expr::Sequence(sequence) => visitor.visit_sequenceexp(sequence),
+        Expr::Lambda(lambda) => visitor.visit_lambdaexp(lambda),
+        Expr::Call(call) => visitor.visit_callexp(call),
+        Expr::Get(get) => visitor.visit_getexp(get),
+        Expr::Index(index) => visitor.visit_indexexp(index),
     }
 }
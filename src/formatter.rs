@@ -0,0 +1,417 @@
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+
+use crate::expr::{Visitor, Assign, Expr, Binary, Grouping, Unary, Variable, walk_expr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::scanner::Trivia;
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While, Test, Function, Return};
+use crate::token::Literal;
+use crate::token_type::TokenType;
+
+const INDENT: &str = "    ";
+
+// Turns a parsed program back into Lox source. Unlike the AstPrinter this is meant to be read
+// (and re-parsed), so comments and blank lines the scanner kept as trivia are woven back in.
+// Numbers keep the spelling they were written with, 0xFF stays 0xFF rather than becoming 255.
+// Expressions are printed in the order they appear in the source, so each number literal
+// takes the next of those spellings.
+pub struct Formatter {
+    trivia: Vec<Trivia>,
+    next_trivia: Cell<usize>, // index of the first trivia not yet written out
+    numbers: Vec<String>,
+    next_number: Cell<usize>,
+    depth: Cell<usize>,
+}
+
+impl StmtVisitor<String> for Formatter {
+    fn visit_expression(&self, e: &Expression) -> String {
+        format!("{};", self.print(&e.expression))
+    }
+
+    fn visit_print(&self, e: &Print) -> String {
+        format!("print {};", self.print(&e.expression))
+    }
+
+    fn visit_var_stmt(&self, e: &StmVariable) -> String {
+        // `var a;` and `var a = nil;` parse to the same thing, the shorter one wins
+        match &e.initializer {
+            Expr::Literal(Literal::Nil) => format!("var {};", e.name.lexeme),
+            initializer => format!("var {} = {};", e.name.lexeme, self.print(initializer)),
+        }
+    }
+
+    fn visit_block_stmt(&self, e: &Block) -> String {
+        // a `for` with an initializer was desugared into { initializer; while ... }
+        if let [initializer, Stmt::While(w)] = e.statements.as_slice()
+            && w.for_loop.is_some_and(|f| f.initializer) {
+            return self.for_loop(Some(initializer), w);
+        }
+        self.block(&e.statements, e.end_line)
+    }
+
+    fn visit_if_stmt(&self, e: &If) -> String {
+        let mut s = format!("if ({}){}", self.print(&e.condition), self.body(&e.then_branch));
+        if let Some(else_branch) = &e.else_branch {
+            // a comment after the then branch stays on its line, the else goes on the next one
+            let then_line = match e.then_branch.as_ref() {
+                Stmt::Block(block) => block.end_line,
+                then_branch => then_branch.line(),
+            };
+            let comments = self.trailing_comments(then_line..=then_line);
+            s.push_str(&comments);
+            match matches!(*e.then_branch, Stmt::Block(_)) && comments.is_empty() {
+                true => s.push(' '),
+                false => s.push_str(&format!("\n{}", self.indent())),
+            }
+            match else_branch.as_ref() {
+                // keep `else if` chains flat instead of nesting them
+                Stmt::If(_) => s.push_str(&format!("else {}", walk_stmt(self, else_branch))),
+                _ => s.push_str(&format!("else{}", self.body(else_branch))),
+            }
+        }
+        s
+    }
+
+    fn visit_while_stmt(&self, e: &While) -> String {
+        if e.for_loop.is_some() {
+            return self.for_loop(None, e);
+        }
+        format!("while ({}){}", self.print(&e.condition), self.body(&e.body))
+    }
+
+    fn visit_test_stmt(&self, e: &Test) -> String {
+        format!("test {} {}", e.name.lexeme, self.block(&e.body, e.end_line))
+    }
+
+    fn visit_function_stmt(&self, e: &Function) -> String {
+        format!("fun {}{} {}", e.name.lexeme, self.params(&e.lambda), self.block(&e.lambda.body, e.lambda.end_line))
+    }
+
+    fn visit_return_stmt(&self, e: &Return) -> String {
+        match &e.value {
+            Some(value) => format!("return {};", self.print(value)),
+            None => "return;".to_string(),
+        }
+    }
+}
+
+impl Visitor<String> for Formatter {
+    fn visit_binaryexp(&self, e: &Binary) -> String {
+        format!("{} {} {}", self.print(&e.left), e.op.lexeme, self.print(&e.right))
+    }
+
+    fn visit_groupingexp(&self, e: &Grouping) -> String {
+        format!("({})", self.print(&e.expression))
+    }
+
+    fn visit_literalexp(&self, e: &Literal) -> String {
+        match e {
+            Literal::String(s) => format!("\"{}\"", s),
+            Literal::Number(_) | Literal::Integer(_) | Literal::BigInt(_) | Literal::Decimal(_) => {
+                let i = self.next_number.get();
+                self.next_number.set(i + 1);
+                self.numbers.get(i).cloned().unwrap_or_else(|| e.to_string())
+            }
+            _ => e.to_string()
+        }
+    }
+
+    fn visit_unaryexp(&self, e: &Unary) -> String {
+        // - -a can't lose its space, --a would be a decrement. !!a and ~~a are fine as they are
+        let right = self.print(&e.right);
+        match e.op.kind == TokenType::Minus && right.starts_with('-') {
+            true => format!("{} {}", e.op.lexeme, right),
+            false => format!("{}{}", e.op.lexeme, right),
+        }
+    }
+
+    fn visit_variableexp(&self, e: &Variable) -> String {
+        e.name.lexeme.clone()
+    }
+
+    fn visit_assignexp(&self, e: &Assign) -> String {
+        format!("{} = {}", e.name.lexeme, self.print(&e.value))
+    }
+
+    fn visit_updateexp(&self, e: &Update) -> String {
+        match (&e.value, e.prefix) {
+            (Some(value), _) => format!("{} {} {}", e.name.lexeme, e.op.lexeme, self.print(value)),
+            (None, true) => format!("{}{}", e.op.lexeme, e.name.lexeme),
+            (None, false) => format!("{}{}", e.name.lexeme, e.op.lexeme),
+        }
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) -> String {
+        format!("{} ? {} : {}", self.print(&e.condition), self.print(&e.then_branch), self.print(&e.else_branch))
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) -> String {
+        format!("{}, {}", self.print(&e.left), self.print(&e.right))
+    }
+
+    fn visit_logicalexp(&self, e: &Logical) -> String {
+        format!("{} {} {}", self.print(&e.left), e.condition.lexeme, self.print(&e.right))
+    }
+
+    fn visit_callexp(&self, e: &Call) -> String {
+        let arguments: Vec<String> = e.arguments.iter().map(|a| match &a.name {
+            Some(name) => format!("{}: {}", name.lexeme, self.print(&a.value)),
+            None => self.print(&a.value),
+        }).collect();
+        format!("{}({})", self.print(&e.callee), arguments.join(", "))
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) -> String {
+        // an arrow's body is the `return` the parser wrapped its value in
+        if e.arrow && let [Stmt::Return(Return { value: Some(value), .. })] = e.body.as_slice() {
+            return format!("{} => {}", self.params(e), self.print(value))
+        }
+        format!("fun {} {}", self.params(e), self.block(&e.body, e.end_line))
+    }
+
+    fn visit_getexp(&self, e: &Get) -> String {
+        format!("{}.{}", self.print(&e.object), e.name.lexeme)
+    }
+
+    fn visit_indexexp(&self, e: &Index) -> String {
+        format!("{}[{}]", self.print(&e.object), self.print(&e.index))
+    }
+}
+
+impl Formatter {
+    pub fn new(trivia: Vec<Trivia>, numbers: Vec<String>) -> Self {
+        Self {
+            trivia,
+            next_trivia: Cell::new(0),
+            numbers,
+            next_number: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+
+    pub fn format(&self, stmts: &[Stmt]) -> String {
+        let mut s = self.statements(stmts, usize::MAX);
+        // whatever is left over sits after the last statement
+        s.extend(self.flush_trivia(usize::MAX, !s.is_empty()));
+        while s.last().is_some_and(|l| l.is_empty()) {
+            s.pop();
+        }
+        if s.is_empty() {
+            return String::new();
+        }
+        s.join("\n") + "\n"
+    }
+
+    fn print(&self, expr: &Expr) -> String {
+        walk_expr(self, expr)
+    }
+
+    fn indent(&self) -> String {
+        INDENT.repeat(self.depth.get())
+    }
+
+    fn statements(&self, stmts: &[Stmt], end_line: usize) -> Vec<String> {
+        // end_line is where whatever holds the statements ends, the `}` of a block
+        let mut lines = Vec::new();
+        for (i, stmt) in stmts.iter().enumerate() {
+            let line = stmt.line();
+            let after_code = !lines.is_empty();
+            lines.extend(self.flush_trivia(line, after_code));
+
+            // comments after code on the statement's lines stay with it, on its last line once it's
+            // printed, even when it spanned several
+            let next_line = stmts.get(i + 1).map_or(end_line, |next| next.line());
+            let s = format!("{}{}", self.indent(), walk_stmt(self, stmt));
+            lines.push(s + &self.trailing_comments(line..=next_line.saturating_sub(1).max(line)));
+        }
+        lines
+    }
+
+    fn trailing_comments(&self, lines: RangeInclusive<usize>) -> String {
+        // the comments that follow code on those lines, each with a space in front
+        let mut s = String::new();
+        while let Some(Trivia::Comment { text, line, trailing: true, .. }) = self.trivia.get(self.next_trivia.get()) {
+            if !lines.contains(line) {
+                break;
+            }
+            s.push(' ');
+            s.push_str(text);
+            self.next_trivia.set(self.next_trivia.get() + 1);
+        }
+        s
+    }
+
+    fn flush_trivia(&self, before_line: usize, after_code: bool) -> Vec<String> {
+        // Writes out every comment that appears before `before_line`. Runs of blank lines
+        // collapse into one and are dropped at the start of a block.
+        let mut lines: Vec<String> = Vec::new();
+        let mut blank = false;
+        while let Some(trivia) = self.trivia.get(self.next_trivia.get()) {
+            match trivia {
+                Trivia::Comment { line, .. } | Trivia::BlankLine(line) if *line >= before_line => break,
+                Trivia::Comment { text, .. } => {
+                    if blank && (after_code || !lines.is_empty()) {
+                        lines.push(String::new());
+                    }
+                    blank = false;
+                    lines.push(format!("{}{}", self.indent(), text));
+                }
+                Trivia::BlankLine(_) => blank = true,
+            }
+            self.next_trivia.set(self.next_trivia.get() + 1);
+        }
+        if blank && (after_code || !lines.is_empty()) {
+            lines.push(String::new());
+        }
+        lines
+    }
+
+    fn block(&self, stmts: &[Stmt], end_line: usize) -> String {
+        self.depth.set(self.depth.get() + 1);
+        let mut lines = self.statements(stmts, end_line);
+        let after_code = !lines.is_empty();
+        lines.extend(self.flush_trivia(end_line, after_code));
+        while lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+        self.depth.set(self.depth.get() - 1);
+
+        if lines.is_empty() {
+            return "{}".to_string();
+        }
+        format!("{{\n{}\n{}}}", lines.join("\n"), self.indent())
+    }
+
+    fn params(&self, lambda: &Lambda) -> String {
+        let params: Vec<String> = lambda.params.iter().map(|p| match (&p.default, p.rest) {
+            (Some(default), _) => format!("{} = {}", p.name.lexeme, self.print(default)),
+            (None, true) => format!("...{}", p.name.lexeme),
+            (None, false) => p.name.lexeme.clone(),
+        }).collect();
+        format!("({})", params.join(", "))
+    }
+
+    fn body(&self, stmt: &Stmt) -> String {
+        // bodies stay on the line of their `if`/`while`, blocks take care of their own layout
+        format!(" {}", walk_stmt(self, stmt))
+    }
+
+    fn for_loop(&self, initializer: Option<&Stmt>, w: &While) -> String {
+        let clauses = w.for_loop.expect("for_loop is only called on desugared loops");
+        let mut s = String::from("for (");
+        match initializer {
+            Some(stmt) => s.push_str(&walk_stmt(self, stmt)),
+            None => s.push(';'),
+        }
+        if clauses.condition {
+            s.push_str(&format!(" {}", self.print(&w.condition)));
+        }
+        s.push(';');
+
+        // the increment was appended to the body, peel it back off
+        let body = match (clauses.increment, w.body.as_ref()) {
+            (true, Stmt::Block(b)) => match b.statements.as_slice() {
+                [body, Stmt::Expression(increment)] => {
+                    s.push_str(&format!(" {}", self.print(&increment.expression)));
+                    body
+                }
+                _ => unreachable!(),
+            },
+            _ => w.body.as_ref(),
+        };
+        s.push(')');
+        s.push_str(&self.body(body));
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    fn format(source: &str) -> String {
+        crate::format_source(source).expect("the script parses")
+    }
+
+    #[test]
+    fn formatting_formatted_code_changes_nothing() {
+        let source = "\
+// a program with a bit of everything
+var a = 0xFF; var b = 1_000.50d;
+fun add(x, y = 2, ...rest) { return x + y; } // adds
+for (var i = 0; i < 3; i = i + 1) { print i; }
+if (a > b) print a; else if (a < b) { print b; } else print nil;
+
+
+var f = (x) => x * 2;
+var g = fun (x) { return - -x; };
+while (true) { break; }
+";
+        let formatted = format(source);
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn comments_stay_where_they_were() {
+        assert_eq!(format("// leading\nprint 1; // trailing\n\n\n// before\nprint 2;\n// at the end\n"),
+            "// leading\nprint 1; // trailing\n\n// before\nprint 2;\n// at the end\n");
+        assert_eq!(format("fun f() {\n// inside\nprint 1;\n// closing\n}\n"),
+            "fun f() {\n    // inside\n    print 1;\n    // closing\n}\n");
+    }
+
+    #[test]
+    fn a_comment_after_the_then_branch_stays_before_the_else() {
+        let source = "if (a) {\n    print 1;\n} // after if\nelse {\n    print 2;\n}\n";
+        assert_eq!(format(source), source);
+        assert_eq!(format("if (a) print 1; // one\nelse print 2;\n"), "if (a) print 1; // one\nelse print 2;\n");
+    }
+
+    #[test]
+    fn comments_inside_a_statement_on_several_lines_end_up_after_it() {
+        assert_eq!(format("var x = 1 // c1\n  + 2; // c2\nprint x;\n"), "var x = 1 + 2; // c1 // c2\nprint x;\n");
+        assert_eq!(format("fun f() {\n    print 1;\n} // end\nprint 2;\n"), "fun f() {\n    print 1;\n} // end\nprint 2;\n");
+    }
+
+    #[test]
+    fn desugared_for_loops_come_back_as_for_loops() {
+        assert_eq!(format("for (var i = 0; i < 3; i = i + 1) print i;"), "for (var i = 0; i < 3; i = i + 1) print i;\n");
+        assert_eq!(format("for (;;) { print 1; }"), "for (;;) {\n    print 1;\n}\n");
+        assert_eq!(format("for (i = 0; i < 3;) print i;"), "for (i = 0; i < 3;) print i;\n");
+        assert_eq!(format("for (; i < 3; i++) {}"), "for (; i < 3; i++) {}\n");
+    }
+
+    #[test]
+    fn numbers_keep_the_spelling_they_were_written_with() {
+        let source = "print 0xFF + 0b1010 + 0o17 + 1_000 + 2.50 + 25e-1 + 2.50d + 99999999999999999999;\n";
+        assert_eq!(format(source), source);
+    }
+
+    #[test]
+    fn repeated_unary_operators_only_keep_a_space_between_minuses() {
+        assert_eq!(format("print !!true;"), "print !!true;\n");
+        assert_eq!(format("print ~~1;"), "print ~~1;\n");
+        assert_eq!(format("print - -1; print - --a; print -!a;"), "print - -1;\nprint - --a;\nprint -!a;\n");
+    }
+
+    #[test]
+    fn check_exits_with_1_when_a_file_is_not_formatted() {
+        let dir = std::env::temp_dir().join(format!("rlox-fmt-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let formatted = dir.join("formatted.lox");
+        let unformatted = dir.join("unformatted.lox");
+        fs::write(&formatted, "print 1;\n").unwrap();
+        fs::write(&unformatted, "print    1;").unwrap();
+        let args = |paths: &[&std::path::Path]| -> Vec<String> {
+            std::iter::once("--check".to_string()).chain(paths.iter().map(|p| p.display().to_string())).collect()
+        };
+
+        assert_eq!(crate::run_fmt(&args(&[&formatted])), 0);
+        assert_eq!(crate::run_fmt(&args(&[&formatted, &unformatted])), 1);
+        assert_eq!(fs::read_to_string(&unformatted).unwrap(), "print    1;", "--check writes nothing");
+        assert_eq!(crate::run_fmt(&args(&[&dir.join("missing.lox")])), 64);
+
+        // without --check the file is formatted in place, after which it passes
+        assert_eq!(crate::run_fmt(&[unformatted.display().to_string()]), 0);
+        assert_eq!(crate::run_fmt(&args(&[&unformatted])), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
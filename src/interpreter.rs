@@ -1,35 +1,198 @@
 
+use std::cmp::Ordering;
 use std::fmt;
-use crate::expr::{Visitor, Expr, Binary, Grouping, Unary, Variable as VariableExpr, walk_expr, Assign, Logical};
-use crate::lox_error;
+use crate::expr::{Visitor, Expr, Binary, Grouping, Unary, Variable as VariableExpr, walk_expr, Assign, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::runtime_error;
 use crate::token::{Literal, Token};
-use crate::object::Object;
+use crate::object::{format_float, Object};
 use crate::token_type::TokenType;
-use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable, Block, If, While};
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable, Block, If, While, Test, Function, Return};
 use crate::environment::{Environment};
-use std::cell::RefCell;
+use crate::callable::{Frame, LoxCallable, LoxFunction};
+use crate::natives;
+use crate::numeric;
+use crate::decimal::Rounding;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::mem;
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 
+// How much of the Rust stack nested calls may use unless the host says otherwise, half of the
+// 2 MiB std gives the threads it spawns. Past it a call fails with a CallDepth error rather
+// than overflowing the stack, which would abort the whole process.
+const DEFAULT_STACK_SIZE: usize = 1024 * 1024;
+
+// a call's callee and its positional and named arguments, evaluated and ready to make the call
+type EvaluatedCall = (Rc<dyn LoxCallable>, Vec<Object>, Vec<(Token, Object)>);
 
 pub struct Interpreter {
     // i am using a refcell since i need to mutate environment in place in the visit_block_stm
     // and I am using RC so it's consistent with Environment.enclosing type
     environment: RefCell<Rc<Environment>>,
+    hooks: Vec<Rc<dyn Hook>>,
+    output: RefCell<Box<dyn Write>>, // where `print` writes to
+    limits: Limits,
+    steps: Cell<u64>,
+    call_depth: Cell<usize>,
+    stack_size: usize,
+    stack_base: Cell<usize>, // where the stack was when the script started, 0 before that
+    line: Cell<usize>, // of the statement running, for errors in expressions, which have no line of their own
+    return_value: RefCell<Object>, // what the `return` being unwound returns
+    tail_call: RefCell<Option<Frame>>, // or the call it hands over to, see call_function
+    tail_position: Cell<bool>, // the call about to be evaluated is what a `return` returns
+    tail_exprs: RefCell<Vec<Expr>>, // tail calls still running, for after_expr once they're done
+    allocated: Cell<usize>,
+    cancel: CancelHandle,
+    capabilities: Capabilities,
+    rounding: Rounding, // for decimal division and round()
+}
+
+// What a script may touch outside the interpreter. Everything is off by default, so an embedder
+// running code it doesn't trust has to opt in. The rlox command line turns it all on.
+#[derive(Clone, Copy, Default)]
+pub struct Capabilities {
+    pub io: bool, // reading and writing files through the `io` module
+}
+
+// Caps on what a script may use, for running code that isn't trusted. Going over one ends the
+// script with a RuntimeError of the matching ErrorKind. None means no limit, which is the default.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,         // statements executed
+    pub max_call_depth: Option<usize>,  // calls in progress at once
+    pub max_memory: Option<usize>,      // bytes of strings, lists and big numbers created while running, all told
+    pub timeout: Option<Duration>,      // wall-clock time, counted from when the script starts
+}
+
+// Stops a running script from another thread, see Interpreter::cancel_handle. The interpreter
+// checks it before every statement and loop iteration and ends the script with an Interrupted
+// error once it's been cancelled or its deadline has passed.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn set_deadline(&self, deadline: Instant) {
+        *self.deadline.lock().unwrap() = Some(deadline);
+    }
+
+    fn check(&self, line: usize) -> Result<(), RuntimeError> {
+        let message = if self.cancelled.load(atomic::Ordering::Relaxed) {
+            "Interrupted"
+        } else if self.deadline.lock().unwrap().is_some_and(|deadline| Instant::now() >= deadline) {
+            "Timed out"
+        } else {
+            return Ok(())
+        };
+        Err(RuntimeError::with_kind(ErrorKind::Interrupted, Token::default(line), message))
+    }
+}
+
+// A Write that keeps what's written to it, for embedders that want a script's output as a String.
+// Clones share the same buffer, so one can go to the interpreter and the other be read from.
+#[derive(Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub fn take(&self) -> String {
+        // everything written since the last take
+        String::from_utf8_lossy(&self.0.take()).into_owned()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Lets tools such as the debugger watch execution, or hold it up, without the interpreter
+// having to know about any of them.
+pub trait Hook {
+    fn start(&self, _interpreter: &Interpreter, _program: &[Stmt]) {}
+
+    fn before_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+
+    fn after_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) {}
+
+    fn before_expr(&self, _interpreter: &Interpreter, _expr: &Expr) {}
+
+    fn after_expr(&self, _interpreter: &Interpreter, _expr: &Expr, _result: &Result<Object, RuntimeError>) {}
+
+    // a block got its own environment, which is the interpreter's current one until the matching pop
+    fn push_environment(&self, _interpreter: &Interpreter) {}
+
+    fn pop_environment(&self, _interpreter: &Interpreter) {}
+
+    // a function written in Lox started running, with a matching exit once it's done however it ends
+    fn enter_function(&self, _interpreter: &Interpreter, _name: &str) {}
+
+    fn exit_function(&self, _interpreter: &Interpreter) {}
+
+    // `arm` is 0 for the then branch of an `if` or `?:`, or a `Logical` that short-circuited,
+    // 1 for the else branch or a `Logical` that went on to its right operand
+    fn branch(&self, _interpreter: &Interpreter, _branch: Branch, _arm: usize) {}
+}
+
+#[derive(Clone, Copy)]
+pub enum Branch<'a> {
+    If(&'a If),
+    Logical(&'a Logical),
+    Conditional(&'a Conditional),
 }
 
 pub struct RuntimeError {
     token: Token,
     message: String,
+    kind: ErrorKind,
+}
+
+// Lets whoever runs a script tell its own errors apart from it hitting a limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Runtime,
+    StepLimit,
+    CallDepth,
+    MemoryLimit,
+    Interrupted, // cancelled through a CancelHandle or out of time
+    // not an error, a `return` unwinding to the function call it's returning from, see
+    // Interpreter::call_function. The parser makes sure one never gets out of a function.
+    Return,
 }
 
 impl RuntimeError {
     pub fn new(token: Token, message: &str) -> Self {
+        Self::with_kind(ErrorKind::Runtime, token, message)
+    }
+
+    pub fn with_kind(kind: ErrorKind, token: Token, message: &str) -> Self {
         RuntimeError {
             token,
             message: message.to_string(),
+            kind,
         }
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
 }
 
 impl fmt::Display for RuntimeError {
@@ -40,25 +203,51 @@ impl fmt::Display for RuntimeError {
 
 impl Visitor<Result<Object, RuntimeError>> for Interpreter{
     fn visit_logicalexp(&self, e: &Logical) -> Result<Object, RuntimeError> {
+        let tail = self.tail_position.replace(false);
         let left = self.evaluate(&e.left)?;
         match e.condition.kind {
             TokenType::Or => {
                 // if left is true then it's true
                 if self.is_truthy(&left) {
+                    self.branch(Branch::Logical(e), 0);
                     return Ok(left)
                 }
             }
             TokenType::And => {
                 // if and is false then it's false
                 if !self.is_truthy(&left){
+                    self.branch(Branch::Logical(e), 0);
                     return Ok(left)
                 }
             }
             _ => unreachable!()
         }
+        self.branch(Branch::Logical(e), 1);
 
         // continue resolving
-        return self.evaluate(&e.right);
+        self.evaluate_tail(&e.right, tail)
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) -> Result<Object, RuntimeError> {
+        // only the branch that's picked is evaluated
+        let tail = self.tail_position.replace(false);
+        if self.is_truthy(&self.evaluate(&e.condition)?) {
+            self.branch(Branch::Conditional(e), 0);
+            self.evaluate_tail(&e.then_branch, tail)
+        } else {
+            self.branch(Branch::Conditional(e), 1);
+            self.evaluate_tail(&e.else_branch, tail)
+        }
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) -> Result<Object, RuntimeError> {
+        let tail = self.tail_position.replace(false);
+        self.evaluate(&e.left)?;
+        self.evaluate_tail(&e.right, tail)
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) -> Result<Object, RuntimeError> {
+        Ok(Object::Callable(Rc::new(LoxFunction::new("lambda", e, self.environment()))))
     }
 
     fn visit_variableexp(&self, e: &VariableExpr) -> Result<Object, RuntimeError> {
@@ -75,50 +264,33 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter{
         let left = self.evaluate(&e.left)?;
         let right = self.evaluate(&e.right)?;
 
+        let error = |message: &str| Err(RuntimeError::new(e.op.clone(), message));
         match e.op.kind {
-            TokenType::Minus => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Number(l-r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a number"))
-            },
-            TokenType::Plus => match (left, right) {
-                // you can either add numbers or concat two strings
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Number(l+r)),
-                (Object::String(l), Object::String(r)) => {
-                    let mut l = l.to_owned();
-                    let r = r.as_str();
-                    l.push_str(r);
-                    Ok(Object::String(l))
+            TokenType::Minus | TokenType::Plus | TokenType::Star | TokenType::Slash |
+            TokenType::Percent | TokenType::TildeSlash | TokenType::StarStar => {
+                self.arithmetic(e.op.kind, &e.op, left, right)
+            }
+            TokenType::Ampersand | TokenType::Pipe | TokenType::Caret |
+            TokenType::LessLess | TokenType::GreaterGreater => {
+                if !numeric::is_number(&left) || !numeric::is_number(&right) {
+                    return error("Operand must be a numbers")
                 }
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers or strings"))
-            },
-            TokenType::Star => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Number(l*r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
-            },
-            TokenType::Slash => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => {
-                    if r == 0.0 {
-                        return Err(RuntimeError::new(e.op.clone(), "attempted to divide by 0"))
-                    }
-                    Ok(Object::Number(l/r))
-                },
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
-            },
-            TokenType::Greater => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Boolean(l > r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
-            },
-            TokenType::GreaterEqual => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Boolean(l >= r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
-            },
-            TokenType::Less => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Boolean(l < r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
-            },
-            TokenType::LessEqual => match (left, right) {
-                (Object::Number(l), Object::Number(r)) => Ok(Object::Boolean(l <= r)),
-                _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a numbers"))
+                let result = numeric::bitwise(e.op.kind, &left, &right).or_else(error)?;
+                self.allocate_number(&result, &e.op)?;
+                Ok(result)
+            }
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+                let Some(ordering) = numeric::compare(&left, &right) else {
+                    return error("Operand must be a numbers")
+                };
+                let wanted: &[Ordering] = match e.op.kind {
+                    TokenType::Greater => &[Ordering::Greater],
+                    TokenType::GreaterEqual => &[Ordering::Greater, Ordering::Equal],
+                    TokenType::Less => &[Ordering::Less],
+                    _ => &[Ordering::Less, Ordering::Equal],
+                };
+                // NaN can't be ordered, so every comparison with it is false
+                Ok(Object::Boolean(ordering.is_some_and(|o| wanted.contains(&o))))
             },
             TokenType::EqualEqual => {
                 Ok(Object::Boolean(self.is_equal(&left, &right)))
@@ -131,8 +303,29 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter{
 
     }
 
+    fn visit_updateexp(&self, e: &Update) -> Result<Object, RuntimeError> {
+        // the variable is read once, before the value is worked out, and written once
+        let old = self.environment.borrow().get(&e.name)?;
+        let value = match &e.value {
+            Some(value) => self.evaluate(value)?,
+            None => Object::Integer(1),
+        };
+        let kind = match e.op.kind {
+            TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+            TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+            TokenType::StarEqual => TokenType::Star,
+            TokenType::SlashEqual => TokenType::Slash,
+            TokenType::PercentEqual => TokenType::Percent,
+            _ => unreachable!("not an update operator"),
+        };
+        let new = self.arithmetic(kind, &e.op, old.clone(), value)?;
+        self.environment.borrow().assign(&e.name, new.clone())?;
+        Ok(if e.prefix { new } else { old })
+    }
+
     fn visit_groupingexp(&self, e: &Grouping) -> Result<Object, RuntimeError> {
-        return self.evaluate(&e.expression)
+        let tail = self.tail_position.replace(false);
+        self.evaluate_tail(&e.expression, tail)
     }
 
     fn visit_literalexp(&self, e: &Literal) -> Result<Object, RuntimeError> {
@@ -141,20 +334,66 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter{
             Literal::Bool(i) => Ok(Object::Boolean(i)),
             Literal::Nil => Ok(Object::Null),
             Literal::Number(i) => Ok(Object::Number(i)),
+            Literal::Integer(i) => Ok(Object::Integer(i)),
+            Literal::BigInt(i) => Ok(Object::BigInt(i)),
+            Literal::Decimal(d) => Ok(Object::Decimal(d)),
             Literal::String(i) => Ok(Object::String(i))
         }
     }
 
+    fn visit_callexp(&self, e: &Call) -> Result<Object, RuntimeError> {
+        let tail = self.tail_position.replace(false);
+        let (function, arguments, named) = self.call_arguments(e)?;
+        if tail && let Some(function) = function.as_function() {
+            // the function returning is done with its frame, so this runs in its place rather than
+            // nested in it, once call_function gets back the `return`. Tail recursion doesn't grow
+            // the stack, and the depth stays the same since the caller's call is over
+            self.call_depth_after(&e.paren)?;
+            self.tail_call.replace(Some(function.bind(self, &e.paren, arguments, named)?));
+            return Ok(Object::Null)
+        }
+        self.call(function.as_ref(), &e.paren, arguments, named)
+    }
+
+    fn visit_getexp(&self, e: &Get) -> Result<Object, RuntimeError> {
+        match self.evaluate(&e.object)? {
+            Object::Module(module) => module.get(&e.name.lexeme).ok_or_else(|| {
+                let message = format!("Undefined property '{}' on module {}", e.name.lexeme, module.name);
+                RuntimeError::new(e.name.clone(), &message)
+            }),
+            _ => Err(RuntimeError::new(e.name.clone(), "Only modules have properties")),
+        }
+    }
+
+    fn visit_indexexp(&self, e: &Index) -> Result<Object, RuntimeError> {
+        let object = self.evaluate(&e.object)?;
+        let index = self.evaluate(&e.index)?;
+        let Object::List(list) = object else {
+            return Err(RuntimeError::new(e.bracket.clone(), "Only lists can be indexed"))
+        };
+        let Object::Integer(index) = index else {
+            return Err(RuntimeError::new(e.bracket.clone(), "Index must be an integer"))
+        };
+        if index < 0 || index as usize >= list.len() {
+            let message = format!("Index {} is out of bounds for a list of length {}", index, list.len());
+            return Err(RuntimeError::new(e.bracket.clone(), &message))
+        }
+        Ok(list[index as usize].clone())
+    }
+
     fn visit_unaryexp(&self, e: &Unary) -> Result<Object, RuntimeError> {
         // Evaluate the right hand expression, but negate if it the operator is !/-.
         let right = self.evaluate(&e.right)?;
         match e.op.kind {
             TokenType::Minus => {
-                match right {
-                    Object::Number(right) => Ok(Object::Number(-right)),
-                    _ => Err(RuntimeError::new(e.op.clone(), "Operand must be a number"))
-                }
+                numeric::negate(&right).ok_or_else(|| RuntimeError::new(e.op.clone(), "Operand must be a number"))
             },
+            TokenType::Tilde => {
+                if !numeric::is_number(&right) {
+                    return Err(RuntimeError::new(e.op.clone(), "Operand must be a number"))
+                }
+                numeric::complement(&right).map_err(|message| RuntimeError::new(e.op.clone(), message))
+            }
             TokenType::Bang => { // shout out zhangbanger
                 Ok(Object::Boolean(!self.is_truthy(&right)))
             }
@@ -167,10 +406,14 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter{
 impl StmtVisitor<Result<(), RuntimeError>> for Interpreter {
     fn visit_if_stmt(&self, stmt: &If) -> Result<(), RuntimeError> {
         if self.is_truthy(&self.evaluate(&stmt.condition)?){
-            self.execute(&stmt.then_branch);
+            self.branch(Branch::If(stmt), 0);
+            self.execute(&stmt.then_branch)?;
         } else {
+            self.branch(Branch::If(stmt), 1);
             // if else_branch is not not then execute it
-            stmt.else_branch.as_ref().map(|e| self.execute(e));
+            if let Some(else_branch) = &stmt.else_branch {
+                self.execute(else_branch)?;
+            }
         }
         Ok(())
     }
@@ -198,98 +441,720 @@ impl StmtVisitor<Result<(), RuntimeError>> for Interpreter {
     }
 
     fn visit_print(&self, stmt: &Print) -> Result<(), RuntimeError> {
-        let obj = self.evaluate(&stmt.expression);
-
-        match self.evaluate(&stmt.expression) {
-            Err(e) => Err(e),
-            Ok(obj) => {
-                println!("{}", self.stringify(&obj));
-                Ok(())
-            }
-        }
+        let obj = self.evaluate(&stmt.expression)?;
+        writeln!(self.output.borrow_mut(), "{}", self.stringify(&obj)).map_err(|err| {
+            RuntimeError::new(Token::default(stmt.line), &format!("Could not print: {}", err))
+        })
     }
 
     fn visit_while_stmt(&self, e: &While) -> Result<(), RuntimeError> {
-        while self.is_truthy(&self.evaluate(&e.condition)?) {
+        // an empty body still has to be stoppable, so check on every turn of the loop
+        loop {
+            self.cancel.check(e.line)?;
+            if !self.is_truthy(&self.evaluate(&e.condition)?) {
+                return Ok(())
+            }
             self.execute(&e.body)?;
         }
+    }
+
+    fn visit_function_stmt(&self, stmt: &Function) -> Result<(), RuntimeError> {
+        // the function closes over the environment it's declared in, which is where its name goes
+        // too, so it can call itself
+        let function = LoxFunction::new(&stmt.name.lexeme, &stmt.lambda, self.environment());
+        self.environment.borrow().define(stmt.name.lexeme.clone(), Object::Callable(Rc::new(function)));
+        Ok(())
+    }
+
+    fn visit_return_stmt(&self, stmt: &Return) -> Result<(), RuntimeError> {
+        let value = match &stmt.value {
+            Some(value) => self.evaluate_tail(value, true)?,
+            None => Object::Null,
+        };
+        self.return_value.replace(value);
+        Err(RuntimeError::with_kind(ErrorKind::Return, stmt.keyword.clone(), "Can't return from top-level code"))
+    }
+
+    fn visit_test_stmt(&self, _stmt: &Test) -> Result<(), RuntimeError> {
+        // tests sit in the script without running, `rlox test --unit` calls run_test for them
         Ok(())
     }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let globals = Environment::new(None);
+        natives::define_globals(&globals);
         Self {
-            environment: RefCell::new(Rc::new(Environment::new(None)))
+            environment: RefCell::new(Rc::new(globals)),
+            hooks: Vec::new(),
+            output: RefCell::new(output),
+            limits: Limits::default(),
+            steps: Cell::new(0),
+            call_depth: Cell::new(0),
+            stack_size: DEFAULT_STACK_SIZE,
+            stack_base: Cell::new(0),
+            line: Cell::new(0),
+            return_value: RefCell::new(Object::Null),
+            tail_call: RefCell::new(None),
+            tail_position: Cell::new(false),
+            tail_exprs: RefCell::new(Vec::new()),
+            allocated: Cell::new(0),
+            cancel: CancelHandle::default(),
+            capabilities: Capabilities::default(),
+            rounding: Rounding::default(),
         }
     }
 
+    pub fn capturing() -> (Self, Captured) {
+        // an interpreter whose prints end up in the returned buffer instead of stdout
+        let captured = Captured::default();
+        (Self::with_output(Box::new(captured.clone())), captured)
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_stack_size(&mut self, bytes: usize) {
+        // the stack the thread running scripts can spare for nested calls
+        self.stack_size = bytes;
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    pub fn add_hook(&mut self, hook: Rc<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn environment(&self) -> Rc<Environment> {
+        Rc::clone(&self.environment.borrow())
+    }
+
     pub fn interpret(&self, stmts: Vec<Stmt>) {
-        for stmt in stmts {
-            match self.execute(&stmt) {
-                Err(e) => {
-                    lox_error(&e.token, &e.message)
+        if let Err(e) = self.try_interpret(&stmts) {
+            runtime_error(e)
+        }
+    }
+
+    pub fn try_interpret(&self, stmts: &[Stmt]) -> Result<(), RuntimeError> {
+        if let Some(timeout) = self.limits.timeout {
+            self.cancel.set_deadline(Instant::now() + timeout);
+        }
+        self.stack_base.set(stack_address());
+        for hook in &self.hooks {
+            hook.start(self, stmts);
+        }
+        // the first runtime error ends the script, like an uncaught exception would
+        stmts.iter().try_for_each(|stmt| self.execute(stmt))
+    }
+
+    pub fn run_test(&self, test: &Test) -> Result<(), RuntimeError> {
+        // each test gets a scope of its own inside the globals the script set up
+        let environment = Rc::new(Environment::new(Some(self.environment())));
+        self.stack_base.set(stack_address());
+        self.execute_block(&test.body, environment)
+    }
+
+    fn execute(&self, stmt: &Stmt) -> Result<(), RuntimeError>{
+        let steps = self.steps.get() + 1;
+        if self.limits.max_steps.is_some_and(|max| steps > max) {
+            let message = format!("Step limit of {} exceeded", steps - 1);
+            return Err(RuntimeError::with_kind(ErrorKind::StepLimit, Token::default(stmt.line()), &message))
+        }
+        self.steps.set(steps);
+        self.cancel.check(stmt.line())?;
+        self.line.set(stmt.line());
+        if self.out_of_stack() {
+            return Err(self.stack_overflow(Token::default(stmt.line())))
+        }
+
+        for hook in &self.hooks {
+            hook.before_stmt(self, stmt)?;
+        }
+        let result = walk_stmt(self, stmt);
+        for hook in &self.hooks {
+            hook.after_stmt(self, stmt);
+        }
+        result
+    }
+
+    pub fn call_function(&self, frame: Frame) -> Result<Object, RuntimeError> {
+        // runs a Lox function's body, its value is whatever a `return` left behind, or nil if
+        // it ran off the end. A `return` that ends in a call leaves that call here instead, and
+        // it runs next in the same loop, however long the chain of tail calls gets
+        let mut frame = frame;
+        let pending = self.tail_exprs.borrow().len();
+        let mut handed_over = pending;
+        let line = self.line.get();
+        let result = loop {
+            for hook in &self.hooks {
+                hook.enter_function(self, &frame.name);
+            }
+            let result = self.execute_block(&frame.body, frame.environment);
+            for hook in &self.hooks {
+                hook.exit_function(self);
+            }
+            match result {
+                Ok(()) => break Ok(Object::Null),
+                Err(err) if err.kind() == ErrorKind::Return => match self.tail_call.take() {
+                    Some(next) => {
+                        // this frame's tail call finishes before the groupings and such around it
+                        let mut exprs = self.tail_exprs.borrow_mut();
+                        exprs[handed_over..].reverse();
+                        handed_over = exprs.len();
+                        frame = next
+                    }
+                    None => break Ok(self.return_value.replace(Object::Null)),
                 },
-                _ => (),
+                Err(err) => break Err(err),
+            }
+        };
+
+        // the tail calls along the way all come to the last one's value, innermost first like
+        // nested calls would
+        self.line.set(line);
+        let exprs = self.tail_exprs.borrow_mut().split_off(pending);
+        for expr in exprs.iter().rev() {
+            for hook in &self.hooks {
+                hook.after_expr(self, expr, &result);
             }
         }
+        result
     }
 
-    fn execute(&self, stmt: &Stmt) -> Result<(), RuntimeError>{
-        return walk_stmt(self, stmt)
+    fn call_arguments(&self, e: &Call) -> Result<EvaluatedCall, RuntimeError> {
+        // the callee and the arguments, positional and named, all evaluated left to right
+        let callee = self.evaluate(&e.callee)?;
+        let mut arguments = Vec::new();
+        let mut named = Vec::new();
+        for argument in &e.arguments {
+            let value = self.evaluate(&argument.value)?;
+            match &argument.name {
+                Some(name) => named.push((name.clone(), value)),
+                None => arguments.push(value),
+            }
+        }
+
+        let Object::Callable(function) = callee else {
+            return Err(RuntimeError::new(e.paren.clone(), "Can only call functions"))
+        };
+        Ok((function, arguments, named))
+    }
+
+    fn call(&self, function: &dyn LoxCallable, paren: &Token, arguments: Vec<Object>, named: Vec<(Token, Object)>) -> Result<Object, RuntimeError> {
+        let depth = self.call_depth_after(paren)?;
+        self.call_depth.set(depth);
+        let result = function.call(self, paren, arguments, named);
+        self.call_depth.set(depth - 1);
+        result
     }
 
-    fn execute_block(&self, statements: &Vec<Stmt>, environment: Rc<Environment>) -> Result<(), RuntimeError>{
+    fn call_depth_after(&self, paren: &Token) -> Result<usize, RuntimeError> {
+        // how deep a call made here runs, as long as the limits leave room for it
+        let depth = self.call_depth.get() + 1;
+        if self.limits.max_call_depth.is_some_and(|max| depth > max) {
+            let message = format!("Call depth limit of {} exceeded", depth - 1);
+            return Err(RuntimeError::with_kind(ErrorKind::CallDepth, paren.clone(), &message))
+        }
+        if self.out_of_stack() {
+            return Err(self.stack_overflow(paren.clone()))
+        }
+        Ok(depth)
+    }
+
+    fn out_of_stack(&self) -> bool {
+        // checked on every call, statement and expression, so however a script nests it fails
+        // with a CallDepth error before it runs out of the stack
+        let base = self.stack_base.get();
+        base != 0 && base.abs_diff(stack_address()) > self.stack_size
+    }
+
+    fn stack_overflow(&self, token: Token) -> RuntimeError {
+        let message = match self.call_depth.get() {
+            0 => "Stack overflow, the code is nested too deeply".to_string(),
+            depth => format!("Stack overflow, calls are nested {} deep", depth),
+        };
+        RuntimeError::with_kind(ErrorKind::CallDepth, token, &message)
+    }
+
+    pub fn evaluate_in(&self, expr: &Expr, environment: Rc<Environment>) -> Result<Object, RuntimeError> {
+        // for a parameter's default, which belongs to the call's environment rather than the caller's
+        let previous = self.environment.replace(environment);
+        let result = self.evaluate(expr);
+        self.environment.replace(previous);
+        result
+    }
+
+    pub fn allocate(&self, bytes: usize, token: &Token) -> Result<(), RuntimeError> {
+        // called before creating a string or a list, so a runaway script stops before it eats the memory
+        let allocated = self.allocated.get() + bytes;
+        if let Some(max) = self.limits.max_memory && allocated > max {
+            let message = format!("Memory limit of {} bytes exceeded", max);
+            return Err(RuntimeError::with_kind(ErrorKind::MemoryLimit, token.clone(), &message))
+        }
+        self.allocated.set(allocated);
+        Ok(())
+    }
+
+    pub fn allocate_list(&self, length: usize, token: &Token) -> Result<(), RuntimeError> {
+        // a list costs its slots, what goes in them is charged when it's made
+        self.allocate(length * mem::size_of::<Object>(), token)
+    }
+
+    fn arithmetic(&self, kind: TokenType, op: &Token, left: Object, right: Object) -> Result<Object, RuntimeError> {
+        // `left kind right` for the arithmetic operators, shared by binary expressions and the
+        // updates like `+=` that do the same sum. `op` is where errors are reported.
+        let error = |message: &str| Err(RuntimeError::new(op.clone(), message));
+        if let (TokenType::Plus, Object::String(l), Object::String(r)) = (kind, &left, &right) {
+            self.allocate(l.len() + r.len(), op)?;
+            let mut l = l.to_owned();
+            l.push_str(r);
+            return Ok(Object::String(l))
+        }
+        if !numeric::is_number(&left) || !numeric::is_number(&right) {
+            return match kind {
+                TokenType::Minus => error("Operand must be a number"),
+                // you can either add numbers or concat two strings
+                TokenType::Plus => error("Operand must be a numbers or strings"),
+                _ => error("Operand must be a numbers"),
+            }
+        }
+        let result = numeric::arithmetic(kind, &left, &right, self.rounding).or_else(error)?;
+        self.allocate_number(&result, op)?;
+        Ok(result)
+    }
+
+    fn allocate_number(&self, number: &Object, token: &Token) -> Result<(), RuntimeError> {
+        // big integers and decimals can grow as large as strings can
+        match number {
+            Object::BigInt(b) => self.allocate(b.bytes(), token),
+            Object::Decimal(d) => self.allocate(d.bytes(), token),
+            _ => Ok(()),
+        }
+    }
+
+    fn branch(&self, branch: Branch, arm: usize) {
+        for hook in &self.hooks {
+            hook.branch(self, branch, arm);
+        }
+    }
+
+    fn execute_block(&self, statements: &[Stmt], environment: Rc<Environment>) -> Result<(), RuntimeError>{
         // temporarily change the enviornment to current block's. Once done revert back to previous bloke.
         let previous = self.environment.replace(environment);
+        for hook in &self.hooks {
+            hook.push_environment(self);
+        }
 
         // this is similar to try finally block from python/java
         let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        for hook in &self.hooks {
+            hook.pop_environment(self);
+        }
         self.environment.replace(previous);
         result
     }
 
-    fn stringify(&self, obj: &Object) -> String {
+    pub fn stringify(&self, obj: &Object) -> String {
         match obj {
             Object::Null => "nil".to_string(),
-            Object::Number(i) => {
-                let mut str = i.to_string();
-                if str.ends_with(".0") {
-                    str.pop();
-                    str.pop();
-                }
-                str
-            }
-            ,
+            Object::Number(i) => format_float(*i),
+            Object::Integer(i) => i.to_string(),
+            Object::BigInt(i) => i.to_string(),
+            Object::Decimal(d) => d.to_string(),
             Object::Boolean(i) => i.to_string(),
             Object::String(i) => i.to_owned(),
+            Object::Callable(f) => format!("<fn {}>", f.name()),
+            Object::List(list) => {
+                let items: Vec<String> = list.iter().map(|item| self.repr(item)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Object::Module(module) => format!("<module {}>", module.name),
+        }
+    }
+
+    pub fn repr(&self, obj: &Object) -> String {
+        // like stringify, but strings keep their quotes so "1" and 1 can be told apart
+        match obj {
+            Object::String(s) => format!("\"{}\"", s),
+            _ => self.stringify(obj),
         }
     }
 
-    fn evaluate(&self, expr: &Expr) -> Result<Object, RuntimeError>{
-        walk_expr(self, expr)
+    pub fn evaluate(&self, expr: &Expr) -> Result<Object, RuntimeError>{
+        if self.out_of_stack() {
+            return Err(self.stack_overflow(Token::default(self.line.get())))
+        }
+        for hook in &self.hooks {
+            hook.before_expr(self, expr);
+        }
+        let result = walk_expr(self, expr);
+        if !self.hooks.is_empty() && self.tail_call.borrow().is_some() {
+            // a tail call or what leads to one, its value comes later, call_function tells the hooks then
+            self.tail_exprs.borrow_mut().push(expr.clone());
+            return result
+        }
+        for hook in &self.hooks {
+            hook.after_expr(self, expr, &result);
+        }
+        result
+    }
+
+    fn evaluate_tail(&self, expr: &Expr, tail: bool) -> Result<Object, RuntimeError> {
+        // what a `return` returns is in tail position, and so is whichever part of it gives the
+        // value: the inside of a grouping, the branches of a conditional, and the right of a
+        // sequence, `and` or `or`. A call there is a tail call, see visit_callexp
+        let leads_to_call = matches!(expr, Expr::Call(_) | Expr::Grouping(_) | Expr::Conditional(_) | Expr::Sequence(_) | Expr::Logical(_));
+        self.tail_position.set(tail && leads_to_call);
+        self.evaluate(expr)
     }
 
-    fn is_truthy(&self, obj: &Object) -> bool{
+    pub fn is_truthy(&self, obj: &Object) -> bool{
         // what is the truth? (Some might sriracha is the best hot sauce).
         // If Object is Null or false then return false, otherwise return true
         match obj {
-            Object::Boolean(i) => i.clone(),
+            Object::Boolean(i) => *i,
             Object::Null => false,
             _ => true
         }
     }
 
-    fn is_equal(&self, left: &Object, right: &Object) -> bool {
-        // if bouth are null then true, if both have same type and return their boolean results
-        // otherwise it would be false. Two items of different types cannot be equal!!!
+    pub fn is_equal(&self, left: &Object, right: &Object) -> bool {
+        // nil equals nil, and other values are equal when they're the same kind with the same
+        // contents. Numbers are the exception, they compare by value across their kinds
         match (left, right) {
             (Object::Null, Object::Null) => true,
             (Object::Boolean(l), Object::Boolean(r)) => l == r,
-            (Object::Number(l), Object::Number(r)) => l == r,
+            // numbers are equal when their values are, whatever their kinds, so 1 == 1.0 == 1.00d
+            (l, r) if numeric::is_number(l) && numeric::is_number(r) => {
+                numeric::compare(l, r) == Some(Some(Ordering::Equal))
+            }
             (Object::String(l), Object::String(r)) => l == r,
+            (Object::Callable(l), Object::Callable(r)) => l == r,
+            (Object::List(l), Object::List(r)) => {
+                l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| self.is_equal(l, r))
+            }
+            (Object::Module(l), Object::Module(r)) => Rc::ptr_eq(l, r),
             _ => false
         }
     }
 
 }
+
+fn stack_address() -> usize {
+    // roughly where the top of the stack is, the stack grows down on some platforms and up on others
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{on_script_thread, parse, run};
+
+    // what the call expressions, and the groupings around them, came to as after_expr saw them
+    #[derive(Default)]
+    struct CallResults(RefCell<Vec<String>>);
+
+    impl Hook for CallResults {
+        fn after_expr(&self, interpreter: &Interpreter, expr: &Expr, result: &Result<Object, RuntimeError>) {
+            match (expr, result) {
+                (Expr::Call(_), Ok(value)) => self.0.borrow_mut().push(interpreter.stringify(value)),
+                (Expr::Grouping(_), Ok(value)) => self.0.borrow_mut().push(format!("({})", interpreter.stringify(value))),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn tail_calls_do_not_grow_the_stack() {
+        let source = "fun loop(n, acc) { if (n == 0) return acc; return loop(n - 1, acc + 1); } print loop(1000000, 0);";
+        assert_eq!(run(source).unwrap(), "1000000\n");
+    }
+
+    #[test]
+    fn hooks_see_tail_calls_with_their_values() {
+        let statements = parse("fun f(n) { if (n == 0) return 7; return f(n - 1); } f(2);").unwrap();
+        let hook = Rc::new(CallResults::default());
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(hook.clone());
+        assert!(interpreter.try_interpret(&statements).is_ok());
+        assert_eq!(*hook.0.borrow(), ["7", "7", "7"]);
+    }
+
+    #[test]
+    fn tail_calls_can_sit_in_groupings_conditionals_and_the_right_of_an_operator() {
+        let sources = [
+            "fun loop(n, acc) { return n == 0 ? acc : loop(n - 1, acc + 1); } print loop(1000000, 0);",
+            "fun loop(n) { if (n == 0) return 0; return (loop(n - 1)); } print loop(1000000);",
+            "var loop = (n) => n == 0 ? 0 : loop(n - 1); print loop(1000000);",
+            "fun loop(n) { return n == 0 or (n, loop(n - 1)); } print loop(1000000);",
+        ];
+        assert_eq!(run(sources[0]).unwrap(), "1000000\n");
+        assert_eq!(run(sources[1]).unwrap(), "0\n");
+        assert_eq!(run(sources[2]).unwrap(), "0\n");
+        assert_eq!(run(sources[3]).unwrap(), "true\n");
+    }
+
+    #[test]
+    fn only_the_part_that_gives_the_value_is_a_tail_call() {
+        // the condition, the left of an operator and the operands of arithmetic all still have work
+        // to do once their call comes back
+        let source = "\
+fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); }
+fun truthy(x) { return x; }
+fun f(n) { return truthy(n) ? (truthy(n), n) : truthy(nil) or truthy(n); }
+print count(3); print f(1); print f(false);
+";
+        assert_eq!(run(source), Ok("3\n1\nfalse\n".to_string()));
+    }
+
+    #[test]
+    fn hooks_see_tail_calls_inside_groupings_innermost_first() {
+        let statements = parse("fun f(n) { if (n == 0) return 7; return (f(n - 1)); } f(2);").unwrap();
+        let hook = Rc::new(CallResults::default());
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(hook.clone());
+        assert!(interpreter.try_interpret(&statements).is_ok());
+        assert_eq!(*hook.0.borrow(), ["7", "(7)", "7", "(7)", "7"]);
+    }
+
+    #[test]
+    fn lists_count_against_the_memory_limit() {
+        let source = "fun f(...xs) { return xs; } f(1, 2, 3, 4, 5, 6, 7, 8);";
+        let err = limited(source, Limits { max_memory: Some(64), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::MemoryLimit);
+        assert_eq!(err.to_string(), "[line 1] Memory limit of 64 bytes exceeded");
+    }
+
+    #[test]
+    fn test_blocks_only_run_when_asked_each_in_a_scope_of_its_own() {
+        let statements = parse("\
+var runs = 0;
+test \"first\" { var x = 1; runs = runs + 1; assert(false); }
+test \"second\" { runs = runs + 1; print x; }
+print runs;
+").unwrap();
+        let (interpreter, printed) = Interpreter::capturing();
+        assert!(interpreter.try_interpret(&statements).is_ok());
+        assert_eq!(printed.take(), "0\n");
+
+        let tests: Vec<&Test> = statements.iter().filter_map(|s| match s { Stmt::Test(t) => Some(t), _ => None }).collect();
+        let results: Vec<String> = tests.iter().map(|t| match interpreter.run_test(t) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => err.to_string(),
+        }).collect();
+        assert_eq!(results, ["[line 2] Assertion failed: got false", "[line 3] Undefined variable 'x'"]);
+        // the failing test didn't stop the one after it
+        assert!(interpreter.try_interpret(&parse("print runs;").unwrap()).is_ok());
+        assert_eq!(printed.take(), "2\n");
+    }
+
+    #[test]
+    fn print_evaluates_once_into_the_captured_output() {
+        let (interpreter, printed) = Interpreter::capturing();
+        let statements = parse("var n = 0; print n = n + 1; print n;").unwrap();
+        assert!(interpreter.try_interpret(&statements).is_ok());
+        assert_eq!(printed.take(), "1\n1\n");
+        assert_eq!(printed.take(), "");
+    }
+
+    #[test]
+    fn compound_assignment_reads_the_target_once_before_the_value() {
+        let source = "\
+var a = 5; a += 2; a -= 1; a *= 3; a /= 4; print a;
+var s = \"ab\"; s += \"c\"; print s;
+var calls = 0;
+var x = 1; x += (calls = calls + 1) + 1; print x; print calls;
+var y = 1; y += (y = 10); print y;
+";
+        assert_eq!(run(source), Ok("4.5\nabc\n3\n1\n11\n".to_string()));
+        assert_eq!(run("var n = nil; n += 1;"), Err("[line 1] Operand must be a numbers or strings".to_string()));
+    }
+
+    #[test]
+    fn prefix_updates_give_the_new_value_and_postfix_the_old() {
+        assert_eq!(run("var i = 1; print i++; print i; print ++i; print i--; print --i;"),
+            Ok("1\n2\n3\n3\n1\n".to_string()));
+    }
+
+    #[test]
+    fn a_conditional_only_evaluates_the_branch_it_picks() {
+        let source = "\
+var seen = \"\";
+print true ? (seen = seen + \"a\") : (seen = seen + \"b\");
+print nil ? (seen = seen + \"c\") : (seen = seen + \"d\");
+print false ? 1 : false ? 2 : 3;
+";
+        assert_eq!(run(source), Ok("a\nad\n3\n".to_string()));
+    }
+
+    #[test]
+    fn a_sequence_evaluates_left_then_right_and_gives_the_right() {
+        let source = "\
+fun say(x) { print x; return x; }
+print (say(1), say(2));
+var a = 1, 2; print a;
+var i = 0; var j = (i = i + 1, i * 10); print j;
+";
+        assert_eq!(run(source), Ok("1\n2\n2\n2\n10\n".to_string()));
+    }
+
+    #[test]
+    fn commas_between_call_arguments_are_not_a_sequence() {
+        let source = "\
+fun pair(a, b) { print a; print b; }
+pair(1, 2);
+pair((1, 2), 3);
+";
+        assert_eq!(run(source), Ok("1\n2\n2\n3\n".to_string()));
+        assert_eq!(run("fun one(a) { return a; } one(1, 2);"), Err("[line 1] Expected 1 arguments but got 2".to_string()));
+    }
+
+    #[test]
+    fn functions_and_arrows_can_be_used_as_values() {
+        let source = "\
+var double = fun (x) { return x * 2; };
+print double(3);
+var triple = (x) => x * 3;
+print triple(3);
+fun apply(f, x) { return f(x); }
+print apply((x) => x + 1, 1);
+print apply(fun (x) { return -x; }, 5);
+var scale = (x, by = 10) => x * by;
+print scale(2); print scale(2, 3);
+";
+        assert_eq!(run(source), Ok("6\n9\n2\n-5\n20\n6\n".to_string()));
+    }
+
+    #[test]
+    fn functions_and_arrows_capture_the_scope_they_are_made_in() {
+        let source = "\
+fun counter() {
+    var count = 0;
+    return fun () { count = count + 1; return count; };
+}
+var a = counter(); var b = counter();
+a(); a();
+print a(); print b();
+fun adder(n) { return (x) => x + n; }
+var add2 = adder(2);
+print add2(40);
+";
+        assert_eq!(run(source), Ok("3\n1\n42\n".to_string()));
+    }
+
+    fn limited(source: &str, limits: Limits) -> RuntimeError {
+        // the error a script that should hit one of the limits stops with
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(limits);
+        interpreter.try_interpret(&parse(source).unwrap()).unwrap_err()
+    }
+
+    #[test]
+    fn tail_calls_count_against_the_call_depth_limit() {
+        let source = "fun f(n) { if (n == 0) return 0; return f(n - 1); } f(5);";
+        let err = limited(source, Limits { max_call_depth: Some(1), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::CallDepth);
+        assert_eq!(err.to_string(), "[line 1] Call depth limit of 1 exceeded");
+    }
+
+    #[test]
+    fn remainder_and_integer_division_truncate() {
+        assert_eq!(run("print -7 % 3; print -7 ~/ 2; print 7.5 % 2; print 2 ** -1;"), Ok("-1\n-3\n1.5\n0.5\n".to_string()));
+        assert_eq!(run("print 1 % 0;"), Err("[line 1] attempted to divide by 0".to_string()));
+    }
+
+    #[test]
+    fn integers_stay_integers_until_mixed_with_a_float() {
+        let source = "print 1 + 2; print 1 + 2.0; print 7 / 2; print 9007199254740993 - 1;";
+        assert_eq!(run(source), Ok("3\n3.0\n3.5\n9007199254740992\n".to_string()));
+    }
+
+    #[test]
+    fn an_endless_loop_stops_at_the_step_limit() {
+        let err = limited("while (true) {}", Limits { max_steps: Some(100), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::StepLimit);
+        assert_eq!(err.to_string(), "[line 1] Step limit of 100 exceeded");
+    }
+
+    #[test]
+    fn growing_a_string_stops_at_the_memory_limit() {
+        let source = "var s = \"ab\"; while (true) s = s + s;";
+        let err = limited(source, Limits { max_memory: Some(1000), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::MemoryLimit);
+        assert_eq!(err.to_string(), "[line 1] Memory limit of 1000 bytes exceeded");
+    }
+
+    #[test]
+    fn nested_calls_stop_at_the_call_depth_limit() {
+        let source = "fun f(n) { return 1 + f(n + 1); }\nf(0);";
+        let err = limited(source, Limits { max_call_depth: Some(10), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::CallDepth);
+        assert_eq!(err.to_string(), "[line 1] Call depth limit of 10 exceeded");
+    }
+
+    #[test]
+    fn unbounded_recursion_errors_before_the_stack_runs_out() {
+        // on the test's own thread, with the default stack budget
+        let err = limited("fun f() { return 1 + f(); }\nf();", Limits::default());
+        assert_eq!(err.kind(), ErrorKind::CallDepth);
+        assert!(err.to_string().starts_with("[line 1] Stack overflow, calls are nested "));
+    }
+
+    #[test]
+    fn deeply_nested_expressions_stop_at_the_stack_budget() {
+        let err = on_script_thread(|| {
+            let statements = parse(&format!("print {}1{};", "(".repeat(250), ")".repeat(250))).unwrap();
+            let (mut interpreter, _) = Interpreter::capturing();
+            interpreter.set_stack_size(32 * 1024);
+            assert!(interpreter.try_interpret(&parse("print 1 + 1;").unwrap()).is_ok());
+            interpreter.try_interpret(&statements).unwrap_err().to_string()
+        });
+        assert_eq!(err, "[line 1] Stack overflow, the code is nested too deeply");
+    }
+
+    #[test]
+    fn cancelling_from_another_thread_stops_an_endless_loop() {
+        let interpreter = Interpreter::new();
+        let handle = interpreter.cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.cancel();
+        });
+        let err = interpreter.try_interpret(&parse("while (true) {}").unwrap()).unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert_eq!(err.to_string(), "[line 1] Interrupted");
+    }
+
+    #[test]
+    fn a_script_past_its_timeout_stops() {
+        let err = limited("while (true) {}", Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() });
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert_eq!(err.to_string(), "[line 1] Timed out");
+    }
+}
@@ -0,0 +1,385 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+// Just enough JSON, and the Content-Length framing around it, for the editor protocols.
+// Objects keep their keys in insertion order so what we send out reads the way it was built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> &Json {
+        // missing keys (or indexing something that isn't an object) read as null, which keeps
+        // lookups like msg.get("params").get("textDocument") short
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v).unwrap_or(&Json::Null),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|n| *n >= 0.0 && n.fract() == 0.0).map(|n| n as usize)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Json::Null)
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { chars: text.chars().collect(), current: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.current != parser.chars.len() {
+            return Err(format!("Unexpected trailing characters at {}", parser.current));
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        Json::Array(items)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl JsonParser {
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("Unexpected character '{}' at {}", c, self.current)),
+            None => Err("Unexpected end of input".to_string()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.whitespace();
+        if self.peek() == Some('}') {
+            self.current += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => return Err(format!("Expected ',' or '}}' at {}", self.current)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(']') {
+            self.current += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(format!("Expected ',' or ']' at {}", self.current)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.advance() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.advance() {
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('u') => {
+                        let mut code = self.hex4()?;
+                        // characters outside the BMP come as a surrogate pair
+                        if (0xD800..0xDC00).contains(&code) && self.peek() == Some('\\') {
+                            self.current += 1;
+                            self.expect('u')?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                        }
+                        s.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    Some(c) => s.push(c),
+                    None => return Err("Unterminated string".to_string()),
+                },
+                Some(c) => s.push(c),
+                None => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        // exactly four hex digits, from_str_radix on its own would take a sign or fewer of them
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.advance().and_then(|c| c.to_digit(16));
+            code = code * 16 + digit.ok_or_else(|| format!("Invalid unicode escape at {}", self.current))?;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.current;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.current += 1;
+        }
+        let text: String = self.chars[start..self.current].iter().collect();
+        text.parse::<f64>().map(Json::Number).map_err(|_| format!("Invalid number '{}'", text))
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.advance() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(format!("Expected '{}' at {}", expected, self.current)),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.current += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.current += 1;
+        c
+    }
+}
+
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    // a message is a few `Name: value` header lines, a blank line and then the body.
+    // None means the other side hung up.
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length header"));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(json: &str) -> Result<String, String> {
+        Json::parse(json).map(|value| value.as_str().expect("a string").to_string())
+    }
+
+    #[test]
+    fn escapes_read_back_as_the_characters_they_stand_for() {
+        assert_eq!(string(r#""a\"b\\c\/d\n\r\t\b\f""#), Ok("a\"b\\c/d\n\r\t\u{8}\u{c}".to_string()));
+        assert_eq!(string(r#""\u0041\u00e9\u20AC""#), Ok("Aé€".to_string()));
+        let written = Json::from("quote \" backslash \\ newline \n bell \u{7}").to_string();
+        assert_eq!(written, r#""quote \" backslash \\ newline \n bell \u0007""#);
+        assert_eq!(string(&written), Ok("quote \" backslash \\ newline \n bell \u{7}".to_string()));
+    }
+
+    #[test]
+    fn a_unicode_escape_takes_exactly_four_hex_digits() {
+        for json in [r#""\u+041""#, r#""\u-041""#, r#""\u041""#, r#""\u00g1""#, r#""\u""#] {
+            assert!(string(json).is_err(), "{} should be refused", json);
+        }
+    }
+
+    #[test]
+    fn surrogate_pairs_make_one_character() {
+        assert_eq!(string(r#""\ud83d\ude00""#), Ok("😀".to_string()));
+        assert_eq!(string(r#""\uD834\uDD1E!""#), Ok("𝄞!".to_string()));
+        // characters outside the BMP are written as they are, not escaped
+        assert_eq!(Json::from("😀").to_string(), "\"😀\"");
+    }
+
+    #[test]
+    fn messages_are_framed_by_their_content_length_in_bytes() {
+        let message = Json::object(vec![("text", "é".into())]);
+        let mut framed = Vec::new();
+        write_message(&mut framed, &message).unwrap();
+        write_message(&mut framed, &Json::Null).unwrap();
+        assert_eq!(String::from_utf8(framed.clone()).unwrap(), "Content-Length: 13\r\n\r\n{\"text\":\"é\"}Content-Length: 4\r\n\r\nnull");
+
+        let mut input = io::Cursor::new(framed);
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{\"text\":\"é\"}"));
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("null"));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn other_headers_are_skipped_and_a_missing_length_is_an_error() {
+        let mut input = io::Cursor::new("Content-Type: application/vscode-jsonrpc\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+        let mut input = io::Cursor::new("Content-Type: application/vscode-jsonrpc\r\n\r\n{}");
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,433 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::expr::{Visitor, Assign, Expr, Binary, Grouping, Unary, Variable, walk_expr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While, Test, Function, Return};
+use crate::token::{Literal, Token};
+use crate::token_type::TokenType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    ShadowedVariable,
+    UndeclaredAssignment,
+    ConstantCondition,
+    SelfComparison,
+    UnreachableCode,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 6] = [
+        Rule::UnusedVariable,
+        Rule::ShadowedVariable,
+        Rule::UndeclaredAssignment,
+        Rule::ConstantCondition,
+        Rule::SelfComparison,
+        Rule::UnreachableCode,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::UndeclaredAssignment => "undeclared-assignment",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::SelfComparison => "self-comparison",
+            Rule::UnreachableCode => "unreachable-code",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|r| r.name() == name)
+    }
+}
+
+pub struct Warning {
+    pub rule: Rule,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] Warning ({}): {}", self.line, self.rule.name(), self.message)
+    }
+}
+
+struct Declaration {
+    line: usize,
+    used: bool,
+}
+
+// Walks the AST looking for code that runs fine but is almost certainly not what was meant.
+pub struct Linter {
+    enabled: HashSet<Rule>,
+    // innermost scope last, the first one holds the globals
+    scopes: RefCell<Vec<HashMap<String, Declaration>>>,
+    // names used before any declaration of them, and whether they were assigned to. Globals are
+    // looked up when the code runs, so a function can use one declared further down the file
+    unresolved: RefCell<Vec<(Token, bool)>>,
+    warnings: RefCell<Vec<Warning>>,
+}
+
+impl StmtVisitor<()> for Linter {
+    fn visit_expression(&self, e: &Expression) {
+        self.check(&e.expression);
+    }
+
+    fn visit_print(&self, e: &Print) {
+        self.check(&e.expression);
+    }
+
+    fn visit_var_stmt(&self, e: &StmVariable) {
+        // the initializer runs before the name exists, `var a = a;` reads the outer `a`
+        self.check(&e.initializer);
+        self.declare(&e.name);
+    }
+
+    fn visit_block_stmt(&self, e: &Block) {
+        self.scopes.borrow_mut().push(HashMap::new());
+        self.lint_stmts(&e.statements);
+        self.end_scope();
+    }
+
+    fn visit_if_stmt(&self, e: &If) {
+        self.check_condition(&e.condition, e.line, "if");
+        walk_stmt(self, &e.then_branch);
+        if let Some(else_branch) = &e.else_branch {
+            walk_stmt(self, else_branch);
+        }
+    }
+
+    fn visit_while_stmt(&self, e: &While) {
+        // a `for` without a condition gets a `true` from the parser, only complain about ones the user wrote
+        let written = e.for_loop.is_none_or(|f| f.condition);
+        if written {
+            let keyword = if e.for_loop.is_some() { "for" } else { "while" };
+            self.check_condition(&e.condition, e.line, keyword);
+        }
+        walk_stmt(self, &e.body);
+    }
+
+    fn visit_test_stmt(&self, e: &Test) {
+        self.scopes.borrow_mut().push(HashMap::new());
+        self.lint_stmts(&e.body);
+        self.end_scope();
+    }
+
+    fn visit_function_stmt(&self, e: &Function) {
+        self.declare(&e.name);
+        self.function(&e.lambda);
+    }
+
+    fn visit_return_stmt(&self, e: &Return) {
+        if let Some(value) = &e.value {
+            self.check(value);
+        }
+    }
+}
+
+impl Visitor<()> for Linter {
+    fn visit_binaryexp(&self, e: &Binary) {
+        if let (Expr::Variable(l), Expr::Variable(r)) = (e.left.as_ref(), e.right.as_ref()) {
+            let comparison = matches!(e.op.kind,
+                TokenType::EqualEqual | TokenType::BangEqual | TokenType::Greater |
+                TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual);
+            if comparison && l.name.lexeme == r.name.lexeme {
+                self.warn(Rule::SelfComparison, e.op.line, format!("'{}' is compared with itself", l.name.lexeme));
+            }
+        }
+        self.check(&e.left);
+        self.check(&e.right);
+    }
+
+    fn visit_groupingexp(&self, e: &Grouping) {
+        self.check(&e.expression);
+    }
+
+    fn visit_literalexp(&self, _e: &Literal) {}
+
+    fn visit_unaryexp(&self, e: &Unary) {
+        self.check(&e.right);
+    }
+
+    fn visit_variableexp(&self, e: &Variable) {
+        let mut scopes = self.scopes.borrow_mut();
+        match scopes.iter_mut().rev().find_map(|s| s.get_mut(&e.name.lexeme)) {
+            Some(declaration) => declaration.used = true,
+            None => self.unresolved.borrow_mut().push((e.name.clone(), false)),
+        }
+    }
+
+    fn visit_assignexp(&self, e: &Assign) {
+        self.check(&e.value);
+        self.assigned(&e.name);
+    }
+
+    fn visit_updateexp(&self, e: &Update) {
+        // only ever counting up a variable doesn't make it used
+        if let Some(value) = &e.value {
+            self.check(value);
+        }
+        self.assigned(&e.name);
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) {
+        self.check_condition(&e.condition, e.question.line, "?:");
+        self.check(&e.then_branch);
+        self.check(&e.else_branch);
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) {
+        self.check(&e.left);
+        self.check(&e.right);
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) {
+        self.function(e);
+    }
+
+    fn visit_logicalexp(&self, e: &Logical) {
+        self.check(&e.left);
+        self.check(&e.right);
+    }
+
+    fn visit_callexp(&self, e: &Call) {
+        self.check(&e.callee);
+        for argument in &e.arguments {
+            self.check(&argument.value);
+        }
+    }
+
+    fn visit_getexp(&self, e: &Get) {
+        self.check(&e.object);
+    }
+
+    fn visit_indexexp(&self, e: &Index) {
+        self.check(&e.object);
+        self.check(&e.index);
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self {
+            enabled: Rule::ALL.into_iter().collect(),
+            scopes: RefCell::new(Vec::new()),
+            unresolved: RefCell::new(Vec::new()),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn enable(&mut self, rule: Rule) {
+        self.enabled.insert(rule);
+    }
+
+    pub fn disable(&mut self, rule: Rule) {
+        self.enabled.remove(&rule);
+    }
+
+    pub fn lint(&self, stmts: &[Stmt]) -> Vec<Warning> {
+        self.scopes.replace(vec![HashMap::new()]);
+        self.lint_stmts(stmts);
+        self.resolve_globals();
+        self.end_scope();
+
+        let mut warnings = self.warnings.take();
+        warnings.sort_by_key(|w| w.line);
+        warnings
+    }
+
+    fn lint_stmts(&self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            walk_stmt(self, stmt);
+        }
+        // whatever follows a `return` in the same block never runs, one warning covers the lot
+        if let Some(i) = stmts.iter().position(|s| matches!(s, Stmt::Return(_)))
+            && let Some(next) = stmts.get(i + 1) {
+            self.warn(Rule::UnreachableCode, next.line(), "Code after 'return' is never run".to_string());
+        }
+    }
+
+    fn function(&self, lambda: &Lambda) {
+        // parameters are part of the function's signature, leaving one unread isn't a mistake
+        self.scopes.borrow_mut().push(HashMap::new());
+        for param in lambda.params.iter() {
+            if let Some(default) = &param.default {
+                self.check(default);
+            }
+            let mut scopes = self.scopes.borrow_mut();
+            let scope = scopes.last_mut().expect("the function's scope was just pushed");
+            scope.insert(param.name.lexeme.clone(), Declaration { line: param.name.line, used: true });
+        }
+        self.lint_stmts(&lambda.body);
+        self.end_scope();
+    }
+
+    fn check(&self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn check_condition(&self, condition: &Expr, line: usize, keyword: &str) {
+        if self.is_constant(condition) {
+            self.warn(Rule::ConstantCondition, line, format!("The condition of this '{}' is always the same", keyword));
+        }
+        self.check(condition);
+    }
+
+    fn is_constant(&self, expr: &Expr) -> bool {
+        // an expression made only of literals can't change between runs
+        match expr {
+            Expr::Literal(_) => true,
+            Expr::Grouping(g) => self.is_constant(&g.expression),
+            Expr::Unary(u) => self.is_constant(&u.right),
+            Expr::Binary(b) => self.is_constant(&b.left) && self.is_constant(&b.right),
+            Expr::Logical(l) => self.is_constant(&l.left) && self.is_constant(&l.right),
+            Expr::Sequence(s) => self.is_constant(&s.left) && self.is_constant(&s.right),
+            Expr::Conditional(c) => {
+                self.is_constant(&c.condition) && self.is_constant(&c.then_branch) && self.is_constant(&c.else_branch)
+            }
+            Expr::Variable(_) | Expr::Assign(_) | Expr::Update(_) | Expr::Lambda(_) | Expr::Call(_) | Expr::Get(_) | Expr::Index(_) => false,
+        }
+    }
+
+    fn assigned(&self, name: &Token) {
+        let declared = self.scopes.borrow().iter().any(|s| s.contains_key(&name.lexeme));
+        if !declared {
+            self.unresolved.borrow_mut().push((name.clone(), true));
+        }
+    }
+
+    fn resolve_globals(&self) {
+        // with the whole program read, what's still unresolved either is a global after all or
+        // was never declared
+        for (name, assigned) in self.unresolved.take() {
+            let mut scopes = self.scopes.borrow_mut();
+            let globals = scopes.first_mut().expect("there is always a global scope");
+            match globals.get_mut(&name.lexeme) {
+                Some(declaration) if !assigned => declaration.used = true,
+                Some(_) => {}
+                None if assigned => self.warn(Rule::UndeclaredAssignment, name.line,
+                    format!("Assignment to undeclared variable '{}'", name.lexeme)),
+                None => {}
+            }
+        }
+    }
+
+    fn declare(&self, name: &Token) {
+        let shadowed = {
+            let scopes = self.scopes.borrow();
+            let (current, outer) = scopes.split_last().expect("there is always a global scope");
+            match current.contains_key(&name.lexeme) {
+                true => None, // a redeclaration, not a new variable hiding an old one
+                false => outer.iter().rev().find_map(|s| s.get(&name.lexeme)).map(|d| d.line),
+            }
+        };
+        if let Some(line) = shadowed {
+            self.warn(Rule::ShadowedVariable, name.line,
+                format!("'{}' shadows the variable declared on line {}", name.lexeme, line));
+        }
+
+        let mut scopes = self.scopes.borrow_mut();
+        let scope = scopes.last_mut().expect("there is always a global scope");
+        scope.insert(name.lexeme.clone(), Declaration { line: name.line, used: false });
+    }
+
+    fn end_scope(&self) {
+        let scope = self.scopes.borrow_mut().pop().unwrap_or_default();
+        let mut unused: Vec<(String, Declaration)> = scope.into_iter()
+            .filter(|(name, d)| !d.used && !name.starts_with('_'))
+            .collect();
+        unused.sort_by_key(|(_, d)| d.line);
+        for (name, declaration) in unused {
+            self.warn(Rule::UnusedVariable, declaration.line, format!("'{}' is never read", name));
+        }
+    }
+
+    fn warn(&self, rule: Rule, line: usize, message: String) {
+        if self.enabled.contains(&rule) {
+            self.warnings.borrow_mut().push(Warning { rule, line, message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse;
+
+    fn lint(source: &str) -> Vec<String> {
+        lint_with(Linter::new(), source)
+    }
+
+    fn lint_with(linter: Linter, source: &str) -> Vec<String> {
+        let statements = parse(source).expect("the script parses");
+        linter.lint(&statements).iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn a_function_can_assign_a_global_declared_after_it() {
+        assert!(lint("fun f() { g = 1; } var g = 0; f(); print g;").is_empty());
+    }
+
+    #[test]
+    fn a_global_read_only_in_an_earlier_function_is_used() {
+        assert!(lint("fun f() { print g; } var g = 0; f();").is_empty());
+    }
+
+    #[test]
+    fn assigning_a_global_that_is_never_declared_still_warns() {
+        assert_eq!(lint("fun f() { g = 1; } f();"),
+            ["[line 1] Warning (undeclared-assignment): Assignment to undeclared variable 'g'"]);
+    }
+
+    #[test]
+    fn a_variable_declared_again_in_an_inner_scope_is_shadowed() {
+        assert_eq!(lint("var a = 1; { var a = 2; print a; } print a;"),
+            ["[line 1] Warning (shadowed-variable): 'a' shadows the variable declared on line 1"]);
+        assert!(lint("{ var a = 1; print a; } { var a = 2; print a; }").is_empty());
+    }
+
+    #[test]
+    fn conditions_that_are_always_the_same_warn() {
+        assert_eq!(lint("if (true) print 1;\nwhile (1 < 2) print 2;\nfor (;true;) { break; }"), [
+            "[line 1] Warning (constant-condition): The condition of this 'if' is always the same",
+            "[line 2] Warning (constant-condition): The condition of this 'while' is always the same",
+            "[line 3] Warning (constant-condition): The condition of this 'for' is always the same",
+        ]);
+        // a `for` with no condition at all loops forever on purpose
+        assert!(lint("for (;;) { break; }").is_empty());
+    }
+
+    #[test]
+    fn comparing_a_variable_with_itself_warns() {
+        assert_eq!(lint("var a = 1; print a == a;"),
+            ["[line 1] Warning (self-comparison): 'a' is compared with itself"]);
+        assert!(lint("var a = 1; var b = 2; print a == b;").is_empty());
+    }
+
+    #[test]
+    fn code_after_a_return_is_unreachable() {
+        assert_eq!(lint("fun f() {\n  return 1;\n  print 2;\n}\nf();"),
+            ["[line 3] Warning (unreachable-code): Code after 'return' is never run"]);
+        assert!(lint("fun f(x) { if (x) return 1; return 2; } f(1);").is_empty());
+    }
+
+    #[test]
+    fn rules_can_be_turned_off_and_back_on() {
+        let source = "var a = 1; print a == a; if (true) print a;";
+        let mut linter = Linter::new();
+        linter.disable(Rule::SelfComparison);
+        assert_eq!(lint_with(linter, source),
+            ["[line 1] Warning (constant-condition): The condition of this 'if' is always the same"]);
+
+        let mut linter = Linter::new();
+        linter.disable(Rule::SelfComparison);
+        linter.disable(Rule::ConstantCondition);
+        linter.enable(Rule::SelfComparison);
+        assert_eq!(lint_with(linter, source), ["[line 1] Warning (self-comparison): 'a' is compared with itself"]);
+        assert_eq!(Rule::from_name("constant-condition"), Some(Rule::ConstantCondition));
+        assert_eq!(Rule::from_name("no-such-rule"), None);
+    }
+}
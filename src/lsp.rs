@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::json::{Json, read_message, write_message};
+use crate::linter::Linter;
+use crate::parser::Parser;
+use crate::resolver::{Resolution, Resolver};
+use crate::scanner::{Scanner, Trivia};
+use crate::stmt::Stmt;
+use crate::token::Token;
+use crate::token_type::TokenType;
+use crate::{collect_diagnostics, Diagnostic};
+
+// Semantic token types we hand out, the index is what goes on the wire.
+const TOKEN_TYPES: [&str; 6] = ["keyword", "variable", "string", "number", "operator", "comment"];
+const COMMENT: usize = 5;
+
+const SYMBOL_KIND_VARIABLE: usize = 13;
+
+// A Language Server Protocol server speaking JSON-RPC over any reader/writer pair, stdin and
+// stdout for `rlox lsp`. Documents are re-analysed from scratch on every request, scripts are
+// small enough for that to be instant.
+pub struct LanguageServer<W: Write> {
+    output: W,
+    documents: HashMap<String, String>, // uri -> text
+    shutdown: bool,
+}
+
+struct Analysis {
+    tokens: Vec<Token>,
+    trivia: Vec<Trivia>,
+    statements: Option<Vec<Stmt>>,
+    resolution: Option<Resolution>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    fn new(text: &str) -> Self {
+        let ((tokens, trivia, statements), diagnostics) = collect_diagnostics(|| {
+            let mut scanner = Scanner::new(text.to_string());
+            let tokens = scanner.scan_tokens().clone();
+            let statements = Parser::new(tokens.clone()).parse();
+            (tokens, scanner.trivia().clone(), statements)
+        });
+        let resolution = statements.as_ref().map(|stmts| Resolver::new().resolve(stmts));
+        Self { tokens, trivia, statements, resolution, diagnostics }
+    }
+}
+
+impl<W: Write> LanguageServer<W> {
+    pub fn new(output: W) -> Self {
+        Self { output, documents: HashMap::new(), shutdown: false }
+    }
+
+    pub fn serve(&mut self, input: &mut impl BufRead) -> io::Result<i32> {
+        // returns the exit code, which tells the client whether we were shut down properly
+        while let Some(body) = read_message(input)? {
+            let message = match Json::parse(&body) {
+                Ok(message) => message,
+                Err(err) => {
+                    self.send_error(&Json::Null, -32700, &err)?;
+                    continue;
+                }
+            };
+            if message.get("method").as_str() == Some("exit") {
+                break;
+            }
+            self.handle(&message)?;
+        }
+        Ok(if self.shutdown { 0 } else { 1 })
+    }
+
+    fn handle(&mut self, message: &Json) -> io::Result<()> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default().to_string();
+
+        match message.get("method").as_str().unwrap_or_default() {
+            "initialize" => self.respond(id, capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                self.respond(id, Json::Null)
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didChange" => {
+                // we only advertise full sync, so the last change holds the whole document
+                if let Some(change) = params.get("contentChanges").as_array().last() {
+                    let text = change.get("text").as_str().unwrap_or_default();
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                let params = Json::object(vec![("uri", uri.into()), ("diagnostics", Json::Array(vec![]))]);
+                self.notify("textDocument/publishDiagnostics", params)
+            }
+            "textDocument/definition" => {
+                let result = self.with_declaration(&uri, params, |text, resolution, index| {
+                    location(&uri, text, &resolution.declarations[index].name)
+                });
+                self.respond(id, result)
+            }
+            "textDocument/references" => {
+                let include_declaration = params.get("context").get("includeDeclaration").as_bool().unwrap_or(true);
+                let result = self.with_declaration(&uri, params, |text, resolution, index| {
+                    let mut locations = Vec::new();
+                    if include_declaration {
+                        locations.push(location(&uri, text, &resolution.declarations[index].name));
+                    }
+                    locations.extend(resolution.references_to(index).map(|t| location(&uri, text, t)));
+                    Json::Array(locations)
+                });
+                self.respond(id, result)
+            }
+            "textDocument/hover" => {
+                let result = self.with_declaration(&uri, params, |text, resolution, index| {
+                    let declaration = &resolution.declarations[index];
+                    let source = text.lines().nth(declaration.name.line - 1).unwrap_or_default().trim();
+                    let scope = if declaration.depth == 0 { "global" } else { "local" };
+                    let value = format!("```lox\n{}\n```\n{} variable declared on line {}", source, scope, declaration.name.line);
+                    Json::object(vec![("contents", Json::object(vec![("kind", "markdown".into()), ("value", value.into())]))])
+                });
+                self.respond(id, result)
+            }
+            "textDocument/documentSymbol" => {
+                let result = self.document_symbols(&uri);
+                self.respond(id, result)
+            }
+            "textDocument/semanticTokens/full" => {
+                let result = self.semantic_tokens(&uri);
+                self.respond(id, result)
+            }
+            _ if !id.is_null() => self.send_error(id, -32601, "Method not found"),
+            _ => Ok(()), // notifications we don't care about, `initialized` among them
+        }
+    }
+
+    fn with_declaration<F>(&self, uri: &str, params: &Json, f: F) -> Json
+    where F: Fn(&str, &Resolution, usize) -> Json
+    {
+        // looks up the declaration of the name under the cursor, null if there isn't one
+        let Some(text) = self.documents.get(uri) else { return Json::Null };
+        let analysis = Analysis::new(text);
+        let Some(resolution) = &analysis.resolution else { return Json::Null };
+        let (line, column) = from_position(text, params.get("position"));
+        match resolution.declaration_at(line, column) {
+            Some(index) => f(text, resolution, index),
+            None => Json::Null,
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let Some(text) = self.documents.get(uri) else { return Ok(()) };
+        let analysis = Analysis::new(text);
+
+        let mut diagnostics: Vec<Json> = analysis.diagnostics.iter().map(|d| {
+            let range = match &d.token {
+                Some(token) if token.kind != TokenType::Eof => token_range(text, token),
+                _ => line_range(text, d.line),
+            };
+            Json::object(vec![
+                ("range", range),
+                ("severity", 1.into()),
+                ("source", "rlox".into()),
+                ("message", d.message.clone().into()),
+            ])
+        }).collect();
+
+        if let Some(statements) = &analysis.statements {
+            for warning in Linter::new().lint(statements) {
+                diagnostics.push(Json::object(vec![
+                    ("range", line_range(text, warning.line)),
+                    ("severity", 2.into()),
+                    ("source", "rlox".into()),
+                    ("code", warning.rule.name().into()),
+                    ("message", warning.message.into()),
+                ]));
+            }
+        }
+
+        let params = Json::object(vec![("uri", uri.into()), ("diagnostics", Json::Array(diagnostics))]);
+        self.notify("textDocument/publishDiagnostics", params)
+    }
+
+    fn document_symbols(&self, uri: &str) -> Json {
+        let Some(text) = self.documents.get(uri) else { return Json::Null };
+        let analysis = Analysis::new(text);
+        let Some(resolution) = &analysis.resolution else { return Json::Array(vec![]) };
+        Json::Array(resolution.declarations.iter().map(|d| Json::object(vec![
+            ("name", d.name.lexeme.clone().into()),
+            ("kind", SYMBOL_KIND_VARIABLE.into()),
+            ("location", location(uri, text, &d.name)),
+        ])).collect())
+    }
+
+    fn semantic_tokens(&self, uri: &str) -> Json {
+        let Some(text) = self.documents.get(uri) else { return Json::Null };
+        let analysis = Analysis::new(text);
+
+        // (line, start, length, type), all zero based and in UTF-16 units like the protocol wants
+        let mut spans: Vec<(usize, usize, usize, usize)> = Vec::new();
+        let mut add = |line: usize, column: usize, lexeme: &str, kind: usize| {
+            // tokens can't span lines, so multi-line strings and comments are sent line by line
+            for (i, part) in lexeme.split('\n').enumerate() {
+                let column = if i == 0 { column } else { 0 };
+                let start = utf16_column(text, line + i, column);
+                let length = part.trim_end_matches('\r').encode_utf16().count();
+                if length > 0 {
+                    spans.push((line + i - 1, start, length, kind));
+                }
+            }
+        };
+        for token in &analysis.tokens {
+            if let Some(kind) = token_type(token.kind) {
+                // a token's line is where it ends, multi-line strings start further up
+                let line = token.line - token.lexeme.matches('\n').count();
+                add(line, token.column, &token.lexeme, kind);
+            }
+        }
+        for trivia in &analysis.trivia {
+            if let Trivia::Comment { text, line, column, .. } = trivia {
+                add(*line, *column, text, COMMENT);
+            }
+        }
+        spans.sort();
+
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for (line, start, length, kind) in spans {
+            let delta_start = if line == previous_line { start - previous_start } else { start };
+            data.extend([line - previous_line, delta_start, length, kind, 0].map(Json::from));
+            (previous_line, previous_start) = (line, start);
+        }
+        Json::object(vec![("data", Json::Array(data))])
+    }
+
+    fn respond(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        let response = Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("result", result)]);
+        self.send(&response)
+    }
+
+    fn send_error(&mut self, id: &Json, code: i32, message: &str) -> io::Result<()> {
+        let error = Json::object(vec![("code", Json::Number(code as f64)), ("message", message.into())]);
+        let response = Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.clone()), ("error", error)]);
+        self.send(&response)
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        let notification = Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)]);
+        self.send(&notification)
+    }
+
+    fn send(&mut self, message: &Json) -> io::Result<()> {
+        write_message(&mut self.output, message)
+    }
+}
+
+fn capabilities() -> Json {
+    let legend = Json::object(vec![
+        ("tokenTypes", Json::Array(TOKEN_TYPES.iter().map(|t| Json::from(*t)).collect())),
+        ("tokenModifiers", Json::Array(vec![])),
+    ]);
+    Json::object(vec![
+        ("capabilities", Json::object(vec![
+            ("textDocumentSync", 1.into()), // full document on every change
+            ("definitionProvider", true.into()),
+            ("referencesProvider", true.into()),
+            ("hoverProvider", true.into()),
+            ("documentSymbolProvider", true.into()),
+            ("semanticTokensProvider", Json::object(vec![("legend", legend), ("full", true.into())])),
+        ])),
+        ("serverInfo", Json::object(vec![("name", "rlox".into())])),
+    ])
+}
+
+fn token_type(kind: TokenType) -> Option<usize> {
+    match kind {
+        TokenType::And | TokenType::Class | TokenType::Else | TokenType::False | TokenType::Fun |
+        TokenType::For | TokenType::If | TokenType::Nil | TokenType::Or | TokenType::Print |
+        TokenType::Return | TokenType::Super | TokenType::This | TokenType::True | TokenType::Var |
+        TokenType::While => Some(0),
+        TokenType::Identifier => Some(1),
+        TokenType::String => Some(2),
+        TokenType::Number => Some(3),
+        TokenType::Minus | TokenType::Plus | TokenType::Slash | TokenType::Star | TokenType::Bang |
+        TokenType::BangEqual | TokenType::Equal | TokenType::EqualEqual | TokenType::Greater |
+        TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual | TokenType::Percent |
+        TokenType::StarStar | TokenType::TildeSlash | TokenType::Ampersand | TokenType::Pipe |
+        TokenType::Caret | TokenType::Tilde | TokenType::LessLess | TokenType::GreaterGreater |
+        TokenType::PlusEqual | TokenType::MinusEqual | TokenType::StarEqual | TokenType::SlashEqual |
+        TokenType::PercentEqual | TokenType::PlusPlus | TokenType::MinusMinus | TokenType::Question |
+        TokenType::Colon | TokenType::Arrow => Some(4),
+        _ => None, // punctuation isn't worth colouring
+    }
+}
+
+fn utf16_column(text: &str, line: usize, column: usize) -> usize {
+    // our columns count characters, the protocol counts UTF-16 code units
+    let source = text.lines().nth(line - 1).unwrap_or_default();
+    source.chars().take(column).map(char::len_utf16).sum()
+}
+
+fn from_position(text: &str, position: &Json) -> (usize, usize) {
+    // an LSP position turned into our 1 based line and character column
+    let line = position.get("line").as_usize().unwrap_or_default();
+    let character = position.get("character").as_usize().unwrap_or_default();
+    let source = text.lines().nth(line).unwrap_or_default();
+    let mut units = 0;
+    let column = source.chars().take_while(|c| {
+        units += c.len_utf16();
+        units <= character
+    }).count();
+    (line + 1, column)
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![("line", (line - 1).into()), ("character", character.into())])
+}
+
+fn token_range(text: &str, token: &Token) -> Json {
+    let start = utf16_column(text, token.line, token.column);
+    let end = start + token.lexeme.encode_utf16().count();
+    Json::object(vec![("start", position(token.line, start)), ("end", position(token.line, end))])
+}
+
+fn line_range(text: &str, line: usize) -> Json {
+    // scanner errors and lint warnings only know their line, so underline all of it
+    let source = text.lines().nth(line.max(1) - 1).unwrap_or_default();
+    let indent = source.len() - source.trim_start().len();
+    let start = source[..indent].encode_utf16().count();
+    let end = source.encode_utf16().count();
+    Json::object(vec![("start", position(line.max(1), start)), ("end", position(line.max(1), end))])
+}
+
+fn location(uri: &str, text: &str, token: &Token) -> Json {
+    Json::object(vec![("uri", uri.into()), ("range", token_range(text, token))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.lox";
+
+    fn open(text: &str) -> Json {
+        let document = Json::object(vec![("uri", URI.into()), ("text", text.into())]);
+        Json::object(vec![("method", "textDocument/didOpen".into()), ("params", Json::object(vec![("textDocument", document)]))])
+    }
+
+    fn at(id: usize, method: &str, line: usize, character: usize) -> Json {
+        // a request about the position `line`:`character`, both zero based
+        let params = Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into())])),
+            ("position", Json::object(vec![("line", line.into()), ("character", character.into())])),
+        ]);
+        Json::object(vec![("id", id.into()), ("method", method.into()), ("params", params)])
+    }
+
+    fn range(line: usize, start: usize, end: usize) -> Json {
+        let position = |character: usize| Json::object(vec![("line", line.into()), ("character", character.into())]);
+        Json::object(vec![("start", position(start)), ("end", position(end))])
+    }
+
+    fn session(messages: &[Json]) -> (i32, Vec<Json>) {
+        // the exit code and everything the server sent back
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut server = LanguageServer::new(Vec::new());
+        let code = server.serve(&mut io::Cursor::new(input)).unwrap();
+        let mut output = io::Cursor::new(server.output);
+        let mut replies = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            replies.push(Json::parse(&body).unwrap());
+        }
+        (code, replies)
+    }
+
+    #[test]
+    fn opening_a_document_publishes_its_errors() {
+        let (_, replies) = session(&[open("print 1 +;")]);
+        let diagnostics = replies[0].get("params").get("diagnostics").as_array();
+        assert_eq!(replies[0].get("method").as_str(), Some("textDocument/publishDiagnostics"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get("severity").as_usize(), Some(1));
+        assert_eq!(diagnostics[0].get("range"), &range(0, 9, 10));
+    }
+
+    #[test]
+    fn definition_and_references_find_the_declaration() {
+        let (_, replies) = session(&[
+            open("var a = 1;\nprint a;\na = a + 1;"),
+            at(1, "textDocument/definition", 1, 6),
+            at(2, "textDocument/references", 2, 4),
+        ]);
+        let location = |line, start, end| Json::object(vec![("uri", URI.into()), ("range", range(line, start, end))]);
+        assert_eq!(replies[1].get("result"), &location(0, 4, 5));
+        assert_eq!(replies[2].get("result"), &Json::Array(vec![
+            location(0, 4, 5),
+            location(1, 6, 7),
+            location(2, 4, 5), // the value is resolved before the variable it's assigned to
+            location(2, 0, 1),
+        ]));
+    }
+
+    #[test]
+    fn hover_shows_where_a_variable_is_declared() {
+        let (_, replies) = session(&[open("{\n  var b = 2;\n  print b;\n}"), at(1, "textDocument/hover", 2, 8)]);
+        let value = replies[1].get("result").get("contents").get("value").as_str();
+        assert_eq!(value, Some("```lox\nvar b = 2;\n```\nlocal variable declared on line 2"));
+    }
+
+    #[test]
+    fn the_exit_code_says_whether_shutdown_came_first() {
+        let shutdown = Json::object(vec![("id", 1.into()), ("method", "shutdown".into())]);
+        let exit = Json::object(vec![("method", "exit".into())]);
+        assert_eq!(session(&[shutdown, exit.clone()]).0, 0);
+        assert_eq!(session(&[exit]).0, 1);
+    }
+
+    #[test]
+    fn semantic_tokens_are_relative_to_the_one_before_in_utf16_units() {
+        let (_, replies) = session(&[
+            open("var a = \"😀\" + 1;\nprint a; // c"),
+            at(1, "textDocument/semanticTokens/full", 0, 0),
+        ]);
+        let data: Vec<usize> = replies[1].get("result").get("data").as_array().iter().map(|n| n.as_usize().unwrap()).collect();
+        assert_eq!(data, [
+            0, 0, 3, 0, 0, // var
+            0, 4, 1, 1, 0, // a
+            0, 2, 1, 4, 0, // =
+            0, 2, 4, 2, 0, // "😀", the emoji is two UTF-16 units
+            0, 5, 1, 4, 0, // +
+            0, 2, 1, 3, 0, // 1
+            1, 0, 5, 0, 0, // print
+            0, 6, 1, 1, 0, // a
+            0, 3, 4, COMMENT, 0, // // c
+        ]);
+    }
+
+    #[test]
+    fn document_symbols_list_every_declaration() {
+        let (_, replies) = session(&[
+            open("var a = 1;\nfun f(x) {\n  var b = x;\n}"),
+            at(1, "textDocument/documentSymbol", 0, 0),
+        ]);
+        let symbols = replies[1].get("result").as_array();
+        let names: Vec<(&str, &Json)> = symbols.iter().map(|s| (s.get("name").as_str().unwrap(), s.get("location").get("range"))).collect();
+        assert_eq!(names, [("a", &range(0, 4, 5)), ("f", &range(1, 4, 5)), ("x", &range(1, 6, 7)), ("b", &range(2, 6, 7))]);
+        assert!(symbols.iter().all(|s| s.get("kind").as_usize() == Some(SYMBOL_KIND_VARIABLE)));
+    }
+}
@@ -1,6 +1,8 @@
 use std::env;
 use std::fs;
-use std::io::{self, Write, BufRead};
+use std::io::{self, Write, BufRead, Read};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 mod scanner;
 mod parser;
@@ -12,11 +14,38 @@ mod interpreter;
 mod object;
 mod stmt;
 mod environment;
+mod formatter;
+mod linter;
+mod json;
+mod resolver;
+mod lsp;
+mod debugger;
+mod callable;
+mod natives;
+mod dap;
+mod profiler;
+mod coverage;
+mod tracer;
+mod test_runner;
+mod bigint;
+mod decimal;
+mod numeric;
+#[cfg(test)]
+mod testing;
 
 use scanner::Scanner;
 use parser::Parser;
+use formatter::Formatter;
+use linter::{Linter, Rule};
+use lsp::LanguageServer;
+use debugger::Debugger;
+use dap::DebugAdapter;
+use profiler::Profiler;
+use coverage::Coverage;
+use tracer::Tracer;
 
-use crate::interpreter::Interpreter;
+use crate::decimal::Rounding;
+use crate::interpreter::{Capabilities, ErrorKind, Interpreter, Hook, Limits};
 use crate::interpreter::RuntimeError;
 use crate::token::Token;
 use crate::token_type::TokenType;
@@ -43,30 +72,166 @@ static PARSER_ERROR_LINE: AtomicIsize = AtomicIsize::new(-1);
 // }
 
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        eprintln!("Usage: jlox [script]");
-        std::process::exit(64);
-    }
+const USAGE: &str = "Usage: rlox [--debug] [--profile] [--profile-stacks <file>]
+            [--coverage] [--coverage-lcov <file>]
+            [--trace] [--trace-lines <from>-<to>] [--trace-file <file>]
+            [--max-steps <n>] [--max-depth <n>] [--max-memory <bytes>] [--timeout <seconds>]
+            [--no-io] [--rounding <mode>] [script]
+       rlox fmt [--check] [files...]
+       rlox lint [--enable <rule>] [--disable <rule>] <files...>
+       rlox test [--unit] <files or directories...>
+       rlox lsp
+       rlox dap [--port <port>]";
 
-    run_file("./test.txt");
+// Scripts run on a thread of their own with this much stack. Every Lox call nests a few
+// walk_stmt/walk_expr frames, so it decides how deep recursion can go before the interpreter
+// stops it with a CallDepth error; an eighth is left for what runs outside of calls. Parsing
+// comes first and needs far less, the parser refuses anything nested too deeply for it.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+const CALL_STACK_SIZE: usize = STACK_SIZE - STACK_SIZE / 8;
 
+fn main() {
+    let runner = std::thread::Builder::new()
+        .name("main".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(run_command)
+        .expect("Failed to start the interpreter thread");
+    if let Err(panic) = runner.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
 
-    // if args.len() == 2 {
-    //     run_file(&args[0]);
-    // } else {
-    //     run_prompt();
-    // }
+fn run_command() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
+    match args.first().map(String::as_str) {
+        Some("fmt") => std::process::exit(run_fmt(&args[1..])),
+        Some("lint") => run_lint(&args[1..]),
+        Some("test") => run_test(&args[1..]),
+        Some("lsp") => {
+            // speaks the Language Server Protocol over stdin/stdout until the editor says goodbye
+            let mut server = LanguageServer::new(io::stdout());
+            let code = server.serve(&mut io::stdin().lock()).unwrap_or_else(|err| {
+                eprintln!("Language server stopped: {}", err);
+                1
+            });
+            std::process::exit(code);
+        }
+        Some("dap") => run_dap(&args[1..]),
+        None => run_prompt(),
+        _ => run_script(&args),
+    }
 }
 
-fn run_file(path: &str){
-    let contents: Vec<u8> = fs::read(path).unwrap_or_else(|err|{
+fn run_script(args: &[String]){
+    let mut debug = false;
+    let mut profile = false;
+    let mut stacks_path = None;
+    let mut coverage = false;
+    let mut lcov_path = None;
+    let mut trace = false;
+    let mut trace_lines = 1..=usize::MAX;
+    let mut trace_path = None;
+    let mut limits = Limits::default();
+    let mut capabilities = Capabilities { io: true };
+    let mut rounding = Rounding::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--profile" => profile = true,
+            "--profile-stacks" if stacks_path.is_none() && args.len() > 0 => {
+                stacks_path = args.next();
+                profile = true;
+            }
+            "--coverage" => coverage = true,
+            "--coverage-lcov" if lcov_path.is_none() && args.len() > 0 => {
+                lcov_path = args.next();
+                coverage = true;
+            }
+            "--trace" => trace = true,
+            "--trace-lines" if args.len() > 0 => {
+                let range = args.next().unwrap();
+                trace_lines = parse_line_range(range).unwrap_or_else(|| {
+                    eprintln!("{} is not a line range, expected something like 10-20", range);
+                    std::process::exit(64);
+                });
+                trace = true;
+            }
+            "--max-steps" if args.len() > 0 => limits.max_steps = Some(parse_limit(arg, args.next().unwrap())),
+            "--max-depth" if args.len() > 0 => limits.max_call_depth = Some(parse_limit(arg, args.next().unwrap())),
+            "--max-memory" if args.len() > 0 => limits.max_memory = Some(parse_limit(arg, args.next().unwrap())),
+            "--timeout" if args.len() > 0 => {
+                let seconds: f64 = parse_limit(arg, args.next().unwrap());
+                limits.timeout = Some(std::time::Duration::try_from_secs_f64(seconds).unwrap_or_else(|_err| {
+                    eprintln!("--timeout expects a number of seconds, got {}", seconds);
+                    std::process::exit(64);
+                }));
+            }
+            "--no-io" => capabilities.io = false,
+            "--rounding" if args.len() > 0 => {
+                rounding = args.next().unwrap().parse().unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(64);
+                });
+            }
+            "--trace-file" if trace_path.is_none() && args.len() > 0 => {
+                trace_path = args.next();
+                trace = true;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(64);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        std::process::exit(64);
+    };
+
+    let contents = fs::read_to_string(path).unwrap_or_else(|_err|{
         eprintln!("Error reading file {}", path);
         std::process::exit(64);
     });
-    run(String::from_utf8(contents).unwrap());
+    let mut hooks: Vec<Rc<dyn Hook>> = Vec::new();
+    if debug {
+        hooks.push(Rc::new(Debugger::new(&contents)));
+    }
+    let profiler = profile.then(|| Rc::new(Profiler::new(&contents)));
+    if let Some(profiler) = &profiler {
+        hooks.push(Rc::clone(profiler) as Rc<dyn Hook>);
+    }
+    if trace {
+        let output: Box<dyn Write> = match trace_path {
+            Some(trace_path) => Box::new(io::BufWriter::new(fs::File::create(trace_path).unwrap_or_else(|_err| {
+                eprintln!("Error writing file {}", trace_path);
+                std::process::exit(64);
+            }))),
+            None => Box::new(io::stderr()),
+        };
+        hooks.push(Rc::new(Tracer::new(output, trace_lines)));
+    }
+    let coverage = coverage.then(|| Rc::new(Coverage::new(path, &contents)));
+    if let Some(coverage) = &coverage {
+        hooks.push(Rc::clone(coverage) as Rc<dyn Hook>);
+    }
+    run(contents, hooks, limits, capabilities, rounding);
+
+    if let Some(profiler) = profiler {
+        eprint!("{}", profiler.report());
+        if let Some(stacks_path) = stacks_path && fs::write(stacks_path, profiler.collapsed_stacks()).is_err() {
+            eprintln!("Error writing file {}", stacks_path);
+        }
+    }
+    if let Some(coverage) = coverage {
+        eprint!("{}", coverage.annotated());
+        if let Some(lcov_path) = lcov_path && fs::write(lcov_path, coverage.lcov()).is_err() {
+            eprintln!("Error writing file {}", lcov_path);
+        }
+    }
     if HAD_ERROR.load(Ordering::Relaxed) {
         std::process::exit(64);
     }
@@ -75,6 +240,171 @@ fn run_file(path: &str){
     }
 }
 
+fn parse_limit<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_err| {
+        eprintln!("{} expects a number, got {}", flag, value);
+        std::process::exit(64);
+    })
+}
+
+fn parse_line_range(range: &str) -> Option<std::ops::RangeInclusive<usize>> {
+    // `10-20`, `10-` or `-20` for open ended ranges, or just `10`
+    let (from, to) = range.split_once('-').unwrap_or((range, range));
+    let from = if from.is_empty() { 1 } else { from.parse().ok()? };
+    let to = if to.is_empty() { usize::MAX } else { to.parse().ok()? };
+    Some(from..=to)
+}
+
+fn run_fmt(args: &[String]) -> i32 {
+    // formats the given files in place, or stdin to stdout when there are none, and gives back the
+    // exit code. With --check nothing is written, the exit code says whether everything is formatted.
+    let check = args.iter().any(|a| a == "--check");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+
+    if paths.is_empty() {
+        let mut source = String::new();
+        if io::stdin().read_to_string(&mut source).is_err() {
+            eprintln!("Error reading stdin");
+            return 64;
+        }
+        let Some(formatted) = format_source(&source) else { return 64 };
+        if check {
+            return if formatted == source { 0 } else { 1 };
+        }
+        print!("{}", formatted);
+        return 0;
+    }
+
+    let mut unformatted = false;
+    for path in paths {
+        let Ok(source) = fs::read_to_string(path) else {
+            eprintln!("Error reading file {}", path);
+            return 64;
+        };
+        let Some(formatted) = format_source(&source) else {
+            eprintln!("Could not format {}", path);
+            unformatted = true;
+            continue;
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted = true;
+        } else if fs::write(path, formatted).is_err() {
+            eprintln!("Error writing file {}", path);
+            return 64;
+        }
+    }
+    if unformatted { 1 } else { 0 }
+}
+
+fn format_source(source: &str) -> Option<String> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().clone();
+    let numbers = tokens.iter()
+        .filter(|t| t.kind == TokenType::Number)
+        .map(|t| t.lexeme.clone())
+        .collect();
+    let statements = Parser::new(tokens).parse()?;
+    if HAD_ERROR.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    Some(Formatter::new(scanner.trivia().clone(), numbers).format(&statements))
+}
+
+fn run_lint(args: &[String]){
+    let mut linter = Linter::new();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--enable" && arg != "--disable" {
+            paths.push(arg);
+            continue;
+        }
+        let rule = args.next().and_then(|name| Rule::from_name(name)).unwrap_or_else(|| {
+            let names: Vec<&str> = Rule::ALL.iter().map(|r| r.name()).collect();
+            eprintln!("{} expects one of: {}", arg, names.join(", "));
+            std::process::exit(64);
+        });
+        match arg.as_str() {
+            "--enable" => linter.enable(rule),
+            _ => linter.disable(rule),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(64);
+    }
+
+    let mut warned = false;
+    for path in paths {
+        let source = fs::read_to_string(path).unwrap_or_else(|_err|{
+            eprintln!("Error reading file {}", path);
+            std::process::exit(64);
+        });
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().clone();
+        let Some(statements) = Parser::new(tokens).parse() else { continue };
+        for warning in linter.lint(&statements) {
+            println!("{}: {}", path, warning);
+            warned = true;
+        }
+    }
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        std::process::exit(64);
+    }
+    if warned {
+        std::process::exit(1);
+    }
+}
+
+fn run_test(args: &[String]){
+    // --unit runs the `test` blocks in the scripts instead of checking their output
+    let unit = args.first().is_some_and(|a| a == "--unit");
+    let paths = if unit { &args[1..] } else { args };
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(64);
+    }
+    let result = match unit {
+        true => test_runner::run_unit(paths),
+        false => test_runner::run_golden(paths),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Error running tests: {}", err);
+            std::process::exit(64);
+        }
+    }
+}
+
+fn run_dap(args: &[String]){
+    match args {
+        [] => {
+            let adapter = DebugAdapter::new(io::BufReader::new(io::stdin()), io::stdout());
+            Rc::new(adapter).serve();
+        }
+        [flag, port] if flag == "--port" => {
+            let port = port.parse::<u16>().unwrap_or_else(|_err| {
+                eprintln!("{} is not a port", port);
+                std::process::exit(64);
+            });
+            if let Err(err) = dap::listen(port) {
+                eprintln!("Debug adapter stopped: {}", err);
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(64);
+        }
+    }
+}
+
 fn run_prompt() {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
@@ -99,8 +429,9 @@ fn run_prompt() {
                 buffer.clear();
             } else {
                 buffer.push_str(&input);
-                run(buffer.clone());
-                buffer.push_str("\n");
+                HAD_ERROR.store(false, Ordering::Relaxed);
+                run(buffer.clone(), Vec::new(), Limits::default(), Capabilities { io: true }, Rounding::default());
+                buffer.push('\n');
                 buffer = remove_line(buffer);
             }
         }
@@ -110,7 +441,7 @@ fn run_prompt() {
     }
 }
 
-fn run(source: String) {
+fn run(source: String, hooks: Vec<Rc<dyn Hook>>, limits: Limits, capabilities: Capabilities, rounding: Rounding) {
     let mut scanner = Scanner::new(source);
     let tokens = scanner.scan_tokens().clone();
     // for token in &tokens {
@@ -119,27 +450,75 @@ fn run(source: String) {
 
     let mut parser = Parser::new(tokens);
     if let Some(statements) = parser.parse() {
-        let printer = ast_printer::AstPrinter;
+        // the scanner reports its errors without stopping the parser, don't run what it garbled
+        if HAD_ERROR.load(Ordering::Relaxed) {
+            return;
+        }
+        let _printer = ast_printer::AstPrinter;
         // println!("{:?}", printer.print_stmts(&statements));
-        let interpreter = Interpreter::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_stack_size(CALL_STACK_SIZE);
+        interpreter.set_limits(limits);
+        interpreter.set_capabilities(capabilities);
+        interpreter.set_rounding(rounding);
+        for hook in hooks {
+            interpreter.add_hook(hook);
+        }
         interpreter.interpret(statements);
-    } else {
-        return
     }
 
 }
 
+pub struct Diagnostic {
+    pub line: usize,
+    pub token: Option<Token>, // the offending token, the scanner only knows the line
+    pub message: String,
+}
+
+thread_local! {
+    // while something like the language server is analysing a document, errors are gathered
+    // here instead of being printed
+    static DIAGNOSTICS: RefCell<Option<Vec<Diagnostic>>> = const { RefCell::new(None) };
+}
+
+fn collect_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    DIAGNOSTICS.with(|d| d.replace(Some(Vec::new())));
+    let result = f();
+    let diagnostics = DIAGNOSTICS.with(|d| d.take()).unwrap_or_default();
+    (result, diagnostics)
+}
+
+fn collect(diagnostic: Diagnostic) -> bool {
+    DIAGNOSTICS.with(|d| match d.borrow_mut().as_mut() {
+        Some(diagnostics) => {
+            diagnostics.push(diagnostic);
+            true
+        }
+        None => false,
+    })
+}
+
 fn lox_error(token: &Token, message: &str){
-    if token.kind == TokenType::Eof {
-        report(&token.line, " at end", message)
-    } else {
-        let _where = &format!("at '{}'", token.lexeme);
-        report(&token.line, _where, message)
+    let _where = match token.kind {
+        TokenType::Eof => " at end".to_string(),
+        _ => format!(" at '{}'", token.lexeme),
+    };
+    let diagnostic = Diagnostic {
+        line: token.line,
+        token: Some(token.clone()),
+        message: format!("Error{}: {}", _where, message),
+    };
+    if !collect(diagnostic) {
+        report(&token.line, &_where, message)
     }
 }
 
 fn report(line: &usize, _where: &str, message: &str){
-    eprintln!("line {} Error {}: {}", line, _where, message);
+    let diagnostic = Diagnostic { line: *line, token: None, message: message.to_string() };
+    if collect(diagnostic) {
+        return;
+    }
+    eprintln!("[line {}] Error{}: {}", line, _where, message);
     HAD_ERROR.store(true, Ordering::Relaxed);
     PARSER_ERROR_LINE.store(*line as isize - 1, Ordering::Relaxed); // scanner is 1 indexed
 }
@@ -155,7 +534,7 @@ fn remove_line(s: String) -> String{
     for (i, l) in s.lines().enumerate(){
         if i != line {
             new_s.push_str(l);
-            new_s.push_str("\n");
+            new_s.push('\n');
         }
     }
     PARSER_ERROR_LINE.store(-1, Ordering::Relaxed);
@@ -165,5 +544,15 @@ fn remove_line(s: String) -> String{
 
 fn runtime_error(err: RuntimeError){
     eprintln!("{}", err);
+    let flag = match err.kind() {
+        ErrorKind::StepLimit => Some("--max-steps"),
+        ErrorKind::MemoryLimit => Some("--max-memory"),
+        ErrorKind::Interrupted => Some("--timeout"),
+        // the stack caps call depth too, past that raising --max-depth doesn't help
+        ErrorKind::CallDepth | ErrorKind::Runtime | ErrorKind::Return => None,
+    };
+    if let Some(flag) = flag {
+        eprintln!("The limit can be raised with {}", flag);
+    }
     HAD_RUNTIME_ERROR.store(true, Ordering::Relaxed);
 }
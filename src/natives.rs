@@ -0,0 +1,190 @@
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::callable::{LoxCallable, NativeFunction};
+use crate::decimal::Decimal;
+use crate::environment::Environment;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::numeric;
+use crate::object::{Module, Object};
+use crate::token::Token;
+
+mod io;
+mod math;
+
+// The functions and modules every program starts out with.
+pub fn define_globals(globals: &Environment) {
+    let natives = [
+        NativeFunction::new("assert", 1, assert),
+        NativeFunction::new("assert_eq", 2, assert_eq),
+        NativeFunction::new("len", 1, len),
+        NativeFunction::new("int", 1, int),
+        NativeFunction::new("float", 1, float),
+        NativeFunction::new("decimal", 1, decimal),
+        NativeFunction::new("round", 2, round),
+    ];
+    for native in natives {
+        globals.define(native.name().to_string(), Object::Callable(Rc::new(native)));
+    }
+    for module in [io::module(), math::module()] {
+        globals.define(module.name.to_string(), Object::Module(Rc::new(module)));
+    }
+}
+
+fn module(name: &'static str, natives: Vec<NativeFunction>) -> Module {
+    let members = natives.into_iter()
+        .map(|native| (native.name().to_string(), Object::Callable(Rc::new(native))))
+        .collect();
+    Module::new(name, members)
+}
+
+fn assert(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // assert(condition) fails unless the condition is truthy
+    let [condition] = arguments.as_slice() else { unreachable!("arity is checked by the caller") };
+    if !interpreter.is_truthy(condition) {
+        let message = format!("Assertion failed: got {}", interpreter.repr(condition));
+        return Err(RuntimeError::new(paren.clone(), &message));
+    }
+    Ok(Object::Null)
+}
+
+fn assert_eq(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // assert_eq(actual, expected) fails unless the two are equal, the way `==` sees it
+    let [actual, expected] = arguments.as_slice() else { unreachable!("arity is checked by the caller") };
+    if !interpreter.is_equal(actual, expected) {
+        let message = format!(
+            "Assertion failed: expected {}, got {}", interpreter.repr(expected), interpreter.repr(actual)
+        );
+        return Err(RuntimeError::new(paren.clone(), &message));
+    }
+    Ok(Object::Null)
+}
+
+fn len(_interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // len(list) or len(string), the latter counting characters rather than bytes
+    match arguments.as_slice() {
+        [Object::List(list)] => Ok(Object::Integer(list.len() as i64)),
+        [Object::String(s)] => Ok(Object::Integer(s.chars().count() as i64)),
+        _ => Err(RuntimeError::new(paren.clone(), "Can only take the length of a list or string")),
+    }
+}
+
+fn int(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // int(x) drops the fraction of a float or decimal and parses a string
+    let [value] = arguments.as_slice() else { unreachable!("arity is checked by the caller") };
+    let converted = match value {
+        Object::Integer(_) | Object::BigInt(_) => Some(value.clone()),
+        Object::Number(n) => BigInt::from_f64(*n).map(numeric::big),
+        Object::Decimal(d) => Some(numeric::big(d.trunc())),
+        Object::String(s) => BigInt::parse(s.trim()).map(numeric::big),
+        _ => None,
+    };
+    converted.ok_or_else(|| {
+        RuntimeError::new(paren.clone(), &format!("Can't convert {} to an integer", interpreter.repr(value)))
+    })
+}
+
+fn float(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // float(x) turns any other number or a string into a float
+    let [value] = arguments.as_slice() else { unreachable!("arity is checked by the caller") };
+    let converted = match value {
+        Object::String(s) => s.trim().parse().ok(),
+        _ => value.as_float(),
+    };
+    converted.map(Object::Number).ok_or_else(|| {
+        RuntimeError::new(paren.clone(), &format!("Can't convert {} to a float", interpreter.repr(value)))
+    })
+}
+
+fn decimal(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // decimal(x) makes an exact decimal from an integer, a string such as "1.10", or a float,
+    // taking the float as the shortest decimal that reads back as it, so decimal(0.1) is 0.1
+    let [value] = arguments.as_slice() else { unreachable!("arity is checked by the caller") };
+    let converted = match value {
+        Object::Decimal(d) => Some(d.clone()),
+        Object::Integer(i) => Some(Decimal::from_integer(BigInt::from_i64(*i))),
+        Object::BigInt(b) => Some(Decimal::from_integer(b.clone())),
+        Object::Number(n) => Decimal::from_f64(*n),
+        Object::String(s) => Decimal::parse(s.trim()),
+        _ => None,
+    };
+    converted.map(Object::Decimal).ok_or_else(|| {
+        RuntimeError::new(paren.clone(), &format!("Can't convert {} to a decimal", interpreter.repr(value)))
+    })
+}
+
+fn round(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // round(x, places) rounds a decimal to exactly that many places, the way the interpreter's
+    // rounding setting says to. Floats are rounded as the decimal they print as.
+    let [value, Object::Integer(places)] = arguments.as_slice() else {
+        return Err(RuntimeError::new(paren.clone(), "Expected a number and a whole number of places"))
+    };
+    let places = match u32::try_from(*places) {
+        Ok(places) if places <= numeric::MAX_PLACES => places,
+        _ if *places < 0 => return Err(RuntimeError::new(paren.clone(), "Places can't be negative")),
+        _ => {
+            let message = format!("Can't round to more than {} places", numeric::MAX_PLACES);
+            return Err(RuntimeError::new(paren.clone(), &message))
+        }
+    };
+    match value {
+        Object::Decimal(d) => Ok(Object::Decimal(d.round(places, interpreter.rounding()))),
+        Object::Integer(_) | Object::BigInt(_) => Ok(value.clone()),
+        Object::Number(n) => {
+            let rounded = Decimal::from_f64(*n)
+                .filter(|d| places < d.places())
+                .map(|d| d.round(places, interpreter.rounding()).to_f64());
+            // inf, NaN and floats with no more places than asked for stay what they are
+            Ok(Object::Number(rounded.unwrap_or(*n)))
+        }
+        _ => Err(RuntimeError::new(paren.clone(), "Can only round numbers")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Rounding;
+    use crate::numeric;
+    use crate::interpreter::Interpreter;
+    use crate::testing::{parse, run};
+
+    #[test]
+    fn assert_fails_on_a_falsy_condition() {
+        assert_eq!(run("assert(1 < 2);"), Ok(String::new()));
+        assert_eq!(run("assert(nil);"), Err("[line 1] Assertion failed: got nil".to_string()));
+    }
+
+    #[test]
+    fn assert_eq_shows_both_values() {
+        assert_eq!(run("assert_eq(1 + 1, 2.0);"), Ok(String::new()));
+        assert_eq!(run("assert_eq(\"1\", 1);"), Err("[line 1] Assertion failed: expected 1, got \"1\"".to_string()));
+    }
+
+    #[test]
+    fn int_and_float_convert_between_the_kinds() {
+        let source = "print int(3.9); print int(-3.9); print int(\" 42 \"); print float(2); print float(\"2.5\");";
+        assert_eq!(run(source), Ok("3\n-3\n42\n2.0\n2.5\n".to_string()));
+        assert_eq!(run("int(\"abc\");"), Err("[line 1] Can't convert \"abc\" to an integer".to_string()));
+        assert_eq!(run("float(nil);"), Err("[line 1] Can't convert nil to a float".to_string()));
+    }
+
+    #[test]
+    fn round_follows_the_interpreters_rounding() {
+        assert_eq!(run("print round(2.5d, 0); print round(2.345d, 2); print round(1.5, 0);"), Ok("2\n2.34\n2.0\n".to_string()));
+        let (mut interpreter, printed) = Interpreter::capturing();
+        interpreter.set_rounding(Rounding::HalfUp);
+        assert!(interpreter.try_interpret(&parse("print round(2.5d, 0);").unwrap()).is_ok());
+        assert_eq!(printed.take(), "3\n");
+    }
+
+    #[test]
+    fn round_refuses_more_places_than_it_can_work_out() {
+        let too_many = format!("[line 1] Can't round to more than {} places", numeric::MAX_PLACES);
+        assert_eq!(run("round(1.5, 4000000000);"), Err(too_many.clone()));
+        assert_eq!(run("round(1.5d, 100000000);"), Err(too_many.clone()));
+        assert_eq!(run("round(1.5d, 100000000000);"), Err(too_many));
+        assert_eq!(run("round(1.5d, -1);"), Err("[line 1] Places can't be negative".to_string()));
+        // a float has no places past the ones it prints with, rounding to more leaves it be
+        assert_eq!(run("print round(1.5, 100000);"), Ok("1.5\n".to_string()));
+    }
+}
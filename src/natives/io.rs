@@ -0,0 +1,153 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::callable::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::object::{Module, Object};
+use crate::token::Token;
+
+// `io`, the file system. Every function fails unless the interpreter was given the io capability,
+// and problems with the files themselves come back as runtime errors the script can be told about.
+pub fn module() -> Module {
+    super::module("io", vec![
+        NativeFunction::new("read_file", 1, read_file),
+        NativeFunction::new("write_file", 2, write_file),
+        NativeFunction::new("append_file", 2, append_file),
+        NativeFunction::new("lines", 1, lines),
+        NativeFunction::new("exists", 1, exists),
+        NativeFunction::new("list_dir", 1, list_dir),
+    ])
+}
+
+fn read_file(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    let path = path(interpreter, paren, &arguments)?;
+    let contents = fs::read_to_string(path).map_err(|err| failed(paren, "read", path, err))?;
+    interpreter.allocate(contents.len(), paren)?;
+    Ok(Object::String(contents))
+}
+
+fn write_file(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    let path = path(interpreter, paren, &arguments)?;
+    fs::write(path, contents(paren, &arguments)?).map_err(|err| failed(paren, "write", path, err))?;
+    Ok(Object::Null)
+}
+
+fn append_file(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // creates the file when it isn't there yet, like write_file would
+    let path = path(interpreter, paren, &arguments)?;
+    let contents = contents(paren, &arguments)?;
+    OpenOptions::new().append(true).create(true).open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| failed(paren, "append to", path, err))?;
+    Ok(Object::Null)
+}
+
+fn lines(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // the file's lines without their line endings
+    let path = path(interpreter, paren, &arguments)?;
+    let contents = fs::read_to_string(path).map_err(|err| failed(paren, "read", path, err))?;
+    interpreter.allocate(contents.len(), paren)?;
+    let lines: Vec<Object> = contents.lines().map(|line| Object::String(line.to_string())).collect();
+    interpreter.allocate_list(lines.len(), paren)?;
+    Ok(Object::List(Rc::new(lines)))
+}
+
+fn exists(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    let path = path(interpreter, paren, &arguments)?;
+    Ok(Object::Boolean(fs::exists(path).map_err(|err| failed(paren, "look for", path, err))?))
+}
+
+fn list_dir(interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    // the names of the entries in a directory, sorted so scripts see the same order everywhere
+    let path = path(interpreter, paren, &arguments)?;
+    let mut names: Vec<String> = fs::read_dir(path)
+        .and_then(|entries| entries.map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned())).collect())
+        .map_err(|err| failed(paren, "list", path, err))?;
+    interpreter.allocate(names.iter().map(String::len).sum(), paren)?;
+    interpreter.allocate_list(names.len(), paren)?;
+    names.sort();
+    Ok(Object::List(Rc::new(names.into_iter().map(Object::String).collect())))
+}
+
+fn path<'a>(interpreter: &Interpreter, paren: &Token, arguments: &'a [Object]) -> Result<&'a str, RuntimeError> {
+    // every function takes the path first, so this is also where file access gets checked
+    if !interpreter.capabilities().io {
+        return Err(RuntimeError::new(paren.clone(), "File access is not allowed"));
+    }
+    match arguments.first() {
+        Some(Object::String(path)) => Ok(path),
+        _ => Err(RuntimeError::new(paren.clone(), "Path must be a string")),
+    }
+}
+
+fn contents<'a>(paren: &Token, arguments: &'a [Object]) -> Result<&'a str, RuntimeError> {
+    match arguments.get(1) {
+        Some(Object::String(contents)) => Ok(contents),
+        _ => Err(RuntimeError::new(paren.clone(), "Contents must be a string")),
+    }
+}
+
+fn failed(paren: &Token, action: &str, path: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::new(paren.clone(), &format!("Could not {} '{}': {}", action, path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::{Capabilities, Interpreter, Limits};
+    use crate::testing::{parse, run};
+
+    fn run_with_io(source: &str) -> Result<String, String> {
+        let (mut interpreter, printed) = Interpreter::capturing();
+        interpreter.set_capabilities(Capabilities { io: true });
+        interpreter.try_interpret(&parse(source).unwrap()).map_err(|err| err.to_string())?;
+        Ok(printed.take())
+    }
+
+    #[test]
+    fn files_are_off_limits_without_the_capability() {
+        assert_eq!(run("io.exists(\"anything\");"), Err("[line 1] File access is not allowed".to_string()));
+    }
+
+    #[test]
+    fn files_can_be_written_appended_and_read_back() {
+        let dir = std::env::temp_dir().join(format!("rlox-io-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let source = format!("\
+var path = \"{dir}/notes.txt\";
+print io.exists(path);
+io.write_file(path, \"one\n\");
+io.append_file(path, \"two\n\");
+print io.read_file(path);
+print io.lines(path);
+print len(io.lines(path));
+io.write_file(\"{dir}/a.txt\", \"\");
+print io.list_dir(\"{dir}\");
+");
+        let printed = run_with_io(&source);
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(printed, Ok("false\none\ntwo\n\n[\"one\", \"two\"]\n2\n[\"a.txt\", \"notes.txt\"]\n".to_string()));
+    }
+
+    #[test]
+    fn problems_with_files_are_runtime_errors() {
+        assert_eq!(run_with_io("io.read_file(\"/no/such/file\");"),
+            Err("[line 1] Could not read '/no/such/file': No such file or directory (os error 2)".to_string()));
+        assert_eq!(run_with_io("io.write_file(1, \"x\");"), Err("[line 1] Path must be a string".to_string()));
+    }
+
+    #[test]
+    fn lists_read_from_files_count_against_the_memory_limit() {
+        // a hundred empty lines are a hundred bytes of text but a much longer list
+        let path = std::env::temp_dir().join(format!("rlox-io-lines-{}.txt", std::process::id()));
+        std::fs::write(&path, "\n".repeat(100)).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_capabilities(Capabilities { io: true });
+        interpreter.set_limits(Limits { max_memory: Some(1000), ..Limits::default() });
+        let source = format!("io.lines(\"{}\");", path.to_str().unwrap());
+        let err = interpreter.try_interpret(&parse(&source).unwrap()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.to_string(), "[line 1] Memory limit of 1000 bytes exceeded");
+    }
+}
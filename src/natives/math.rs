@@ -0,0 +1,87 @@
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+
+use crate::callable::NativeFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::numeric;
+use crate::object::{Module, Object};
+use crate::token::Token;
+
+// `math`, the usual functions on numbers plus `pi` and `inf`. Angles are in radians. Any kind of
+// number is taken wherever a float is, abs, min and max keep the kind they were given and the
+// rest give floats.
+pub fn module() -> Module {
+    let mut math = super::module("math", vec![
+        NativeFunction::new("sqrt", 1, |_, paren, arguments| unary(paren, &arguments, f64::sqrt)),
+        NativeFunction::new("floor", 1, |_, paren, arguments| unary(paren, &arguments, f64::floor)),
+        NativeFunction::new("ceil", 1, |_, paren, arguments| unary(paren, &arguments, f64::ceil)),
+        NativeFunction::new("round", 1, |_, paren, arguments| unary(paren, &arguments, f64::round)),
+        NativeFunction::new("abs", 1, abs),
+        NativeFunction::new("sin", 1, |_, paren, arguments| unary(paren, &arguments, f64::sin)),
+        NativeFunction::new("cos", 1, |_, paren, arguments| unary(paren, &arguments, f64::cos)),
+        NativeFunction::new("tan", 1, |_, paren, arguments| unary(paren, &arguments, f64::tan)),
+        NativeFunction::new("asin", 1, |_, paren, arguments| unary(paren, &arguments, f64::asin)),
+        NativeFunction::new("acos", 1, |_, paren, arguments| unary(paren, &arguments, f64::acos)),
+        NativeFunction::new("atan", 1, |_, paren, arguments| unary(paren, &arguments, f64::atan)),
+        NativeFunction::new("pow", 2, |_, paren, arguments| binary(paren, &arguments, f64::powf)),
+        NativeFunction::new("min", 2, |_, paren, arguments| pick(paren, &arguments, Ordering::Less)),
+        NativeFunction::new("max", 2, |_, paren, arguments| pick(paren, &arguments, Ordering::Greater)),
+        NativeFunction::new("atan2", 2, |_, paren, arguments| binary(paren, &arguments, f64::atan2)),
+    ]);
+    math.define("pi", Object::Number(PI));
+    math.define("inf", Object::Number(f64::INFINITY));
+    math
+}
+
+fn unary(paren: &Token, arguments: &[Object], f: fn(f64) -> f64) -> Result<Object, RuntimeError> {
+    match arguments.first().and_then(Object::as_float) {
+        Some(x) => Ok(Object::Number(f(x))),
+        None => Err(RuntimeError::new(paren.clone(), "Argument must be a number")),
+    }
+}
+
+fn binary(paren: &Token, arguments: &[Object], f: fn(f64, f64) -> f64) -> Result<Object, RuntimeError> {
+    match (arguments[0].as_float(), arguments[1].as_float()) {
+        (Some(x), Some(y)) => Ok(Object::Number(f(x, y))),
+        _ => Err(RuntimeError::new(paren.clone(), "Arguments must be numbers")),
+    }
+}
+
+fn abs(_interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+    match arguments.as_slice() {
+        [Object::Integer(i)] if *i < 0 => Ok(numeric::negate(&arguments[0]).expect("an integer negates")),
+        [Object::Integer(_)] => Ok(arguments[0].clone()),
+        [Object::BigInt(b)] => Ok(numeric::big(b.abs())),
+        [Object::Decimal(d)] => Ok(Object::Decimal(d.abs())),
+        _ => unary(paren, &arguments, f64::abs),
+    }
+}
+
+fn pick(paren: &Token, arguments: &[Object], wanted: Ordering) -> Result<Object, RuntimeError> {
+    // min or max, the one of two numbers that compares as `wanted` to the other. NaN wins, so
+    // it isn't quietly lost.
+    let [x, y] = arguments else { unreachable!("arity is checked by the caller") };
+    match numeric::compare(x, y) {
+        Some(Some(ordering)) => Ok(if ordering == wanted { x.clone() } else { y.clone() }),
+        Some(None) => Ok(Object::Number(f64::NAN)),
+        None => Err(RuntimeError::new(paren.clone(), "Arguments must be numbers")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn functions_and_constants() {
+        let source = "print math.sqrt(16); print math.floor(-1.5); print math.abs(-3); print math.pow(2, 3);
+            print math.max(2, 3.5); print math.min(2, 3.5); print math.pi; print math.inf;";
+        assert_eq!(run(source), Ok("4.0\n-2.0\n3\n8.0\n3.5\n2\n3.141592653589793\ninf\n".to_string()));
+    }
+
+    #[test]
+    fn operands_must_be_numbers() {
+        assert_eq!(run("math.sqrt(\"16\");"), Err("[line 1] Argument must be a number".to_string()));
+        assert_eq!(run("math.max(1, nil);"), Err("[line 1] Arguments must be numbers".to_string()));
+    }
+}
@@ -0,0 +1,331 @@
+use std::cmp::Ordering;
+
+use crate::bigint::BigInt;
+use crate::decimal::{Decimal, Rounding};
+use crate::object::Object;
+use crate::token_type::TokenType;
+
+// Arithmetic across the kinds of number. From narrowest to widest they are integers, big integers
+// and decimals, and an operation works in the widest kind of its two operands. Floats are off to
+// the side: mixed with an integer the result is a float, mixed with a decimal it's an error, since
+// quietly making an exact value inexact is what decimals are there to avoid.
+
+// the largest result `**` will work out, a bit over a million digits
+const MAX_POWER_BITS: usize = 1 << 22;
+
+// the most places round() pads a decimal out to, which takes a power of ten about that large
+pub const MAX_PLACES: u32 = (MAX_POWER_BITS / 10 * 3) as u32;
+
+enum Pair {
+    Integers(i64, i64),
+    BigInts(BigInt, BigInt),
+    Decimals(Decimal, Decimal),
+    Floats(f64, f64),
+}
+
+pub fn is_number(obj: &Object) -> bool {
+    matches!(obj, Object::Integer(_) | Object::BigInt(_) | Object::Decimal(_) | Object::Number(_))
+}
+
+pub fn arithmetic(op: TokenType, left: &Object, right: &Object, rounding: Rounding) -> Result<Object, &'static str> {
+    // `left op right` for two numbers, the error is the message to report at the operator
+    match pair(left, right)? {
+        Pair::Integers(l, r) => integers(op, l, r),
+        Pair::BigInts(l, r) => bigints(op, l, r),
+        Pair::Decimals(l, r) => decimals(op, l, r, rounding),
+        Pair::Floats(l, r) => floats(op, l, r),
+    }
+}
+
+pub fn negate(operand: &Object) -> Option<Object> {
+    match operand {
+        Object::Integer(i) => Some(i.checked_neg().map_or_else(|| big(BigInt::from_i64(*i).neg()), Object::Integer)),
+        Object::BigInt(b) => Some(big(b.neg())),
+        Object::Decimal(d) => Some(Object::Decimal(d.neg())),
+        Object::Number(n) => Some(Object::Number(-n)),
+        _ => None,
+    }
+}
+
+pub fn bitwise(op: TokenType, left: &Object, right: &Object) -> Result<Object, &'static str> {
+    // `&`, `|`, `^`, `<<` and `>>` on whole numbers of any kind, as if they were two's complement
+    // of whatever width it takes. Shifting right floors, so -5 >> 1 is -3.
+    if let (Object::Integer(l), Object::Integer(r)) = (left, right) {
+        let (l, r) = (*l, *r);
+        match op {
+            TokenType::Ampersand => return Ok(Object::Integer(l & r)),
+            TokenType::Pipe => return Ok(Object::Integer(l | r)),
+            TokenType::Caret => return Ok(Object::Integer(l ^ r)),
+            TokenType::LessLess | TokenType::GreaterGreater if r < 0 => return Err("Can't shift by a negative amount"),
+            // only when no bits fall off the top, otherwise it's a big integer
+            TokenType::LessLess if r < 63 && (l << r) >> r == l => return Ok(Object::Integer(l << r)),
+            TokenType::GreaterGreater => return Ok(Object::Integer(l >> r.min(63))),
+            _ => {}
+        }
+    }
+    let (l, r) = (whole(left)?, whole(right)?);
+    Ok(big(match op {
+        TokenType::Ampersand => l.bitwise(&r, |a, b| a & b),
+        TokenType::Pipe => l.bitwise(&r, |a, b| a | b),
+        TokenType::Caret => l.bitwise(&r, |a, b| a ^ b),
+        TokenType::LessLess | TokenType::GreaterGreater if r.is_negative() => return Err("Can't shift by a negative amount"),
+        TokenType::LessLess => {
+            let bits = r.to_i64().map(|r| r as usize).filter(|&r| l.bits().saturating_add(r) <= MAX_POWER_BITS);
+            l.shl(bits.ok_or("Result of '<<' is too large")?)
+        }
+        TokenType::GreaterGreater => l.shr(r.to_i64().map_or(usize::MAX, |r| r as usize)),
+        _ => unreachable!("not a bitwise operator"),
+    }))
+}
+
+pub fn complement(operand: &Object) -> Result<Object, &'static str> {
+    // `~x`, which in two's complement is -x - 1
+    match operand {
+        Object::Integer(i) => Ok(Object::Integer(!i)),
+        _ => Ok(big(whole(operand)?.neg().sub(&BigInt::from_i64(1)))),
+    }
+}
+
+pub fn compare(left: &Object, right: &Object) -> Option<Option<Ordering>> {
+    // None when either isn't a number, Some(None) when they can't be ordered, which only
+    // happens with NaN. Unlike arithmetic every pair compares exactly, floats included.
+    let float_against = |f: f64, other: &Object| -> Option<Ordering> {
+        // how `other` compares to `f`
+        if f.is_nan() {
+            return None
+        }
+        if f.is_infinite() {
+            return Some(if f > 0.0 { Ordering::Less } else { Ordering::Greater })
+        }
+        exact(other).partial_cmp(&Decimal::from_f64_exact(f)?)
+    };
+    Some(match (left, right) {
+        (Object::Number(l), Object::Number(r)) => l.partial_cmp(r),
+        (Object::Number(l), r) if is_number(r) => float_against(*l, r).map(Ordering::reverse),
+        (l, Object::Number(r)) if is_number(l) => float_against(*r, l),
+        (Object::Integer(l), Object::Integer(r)) => Some(l.cmp(r)),
+        (l, r) if is_number(l) && is_number(r) => exact(l).partial_cmp(&exact(r)),
+        _ => return None,
+    })
+}
+
+pub fn big(b: BigInt) -> Object {
+    // a big integer goes back to being an ordinary one whenever it fits
+    b.to_i64().map_or(Object::BigInt(b), Object::Integer)
+}
+
+fn pair(left: &Object, right: &Object) -> Result<Pair, &'static str> {
+    Ok(match (left, right) {
+        (Object::Number(_), Object::Decimal(_)) | (Object::Decimal(_), Object::Number(_)) => {
+            return Err("Can't mix decimals and floats, convert one with decimal() or float()")
+        }
+        (Object::Integer(l), Object::Integer(r)) => Pair::Integers(*l, *r),
+        (Object::Decimal(_), _) | (_, Object::Decimal(_)) => Pair::Decimals(exact(left), exact(right)),
+        (Object::Number(_), _) | (_, Object::Number(_)) => Pair::Floats(
+            left.as_float().ok_or("Operand must be a number")?,
+            right.as_float().ok_or("Operand must be a number")?,
+        ),
+        _ => Pair::BigInts(integer(left).ok_or("Operand must be a number")?, integer(right).ok_or("Operand must be a number")?),
+    })
+}
+
+fn integer(obj: &Object) -> Option<BigInt> {
+    match obj {
+        Object::Integer(i) => Some(BigInt::from_i64(*i)),
+        Object::BigInt(b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+fn whole(obj: &Object) -> Result<BigInt, &'static str> {
+    // a number with nothing after the point, as an integer, for the bitwise operators
+    let fractional = Err("Operands of bitwise operators must be whole numbers");
+    match obj {
+        Object::Number(n) if n.fract() != 0.0 => fractional,
+        Object::Number(n) => BigInt::from_f64(*n).ok_or("Operands of bitwise operators must be whole numbers"),
+        Object::Decimal(d) => match d.div_rem(&Decimal::from_integer(BigInt::from_i64(1))) {
+            Some((integer, fraction)) if fraction.is_zero() => Ok(integer),
+            _ => fractional,
+        },
+        _ => integer(obj).ok_or("Operand must be a number"),
+    }
+}
+
+fn exact(obj: &Object) -> Decimal {
+    // any number other than a float, as a decimal
+    match obj {
+        Object::Decimal(d) => d.clone(),
+        _ => Decimal::from_integer(integer(obj).expect("only called on integers and decimals")),
+    }
+}
+
+fn integers(op: TokenType, l: i64, r: i64) -> Result<Object, &'static str> {
+    // the common case, only falling back to big integers when a result doesn't fit
+    let result = match op {
+        TokenType::Plus => l.checked_add(r),
+        TokenType::Minus => l.checked_sub(r),
+        TokenType::Star => l.checked_mul(r),
+        TokenType::Slash => return floats(op, l as f64, r as f64),
+        TokenType::Percent | TokenType::TildeSlash if r == 0 => return Err("attempted to divide by 0"),
+        TokenType::Percent => l.checked_rem(r),
+        TokenType::TildeSlash => l.checked_div(r),
+        // a negative exponent makes a fraction, which only a float can hold
+        TokenType::StarStar if r < 0 => return floats(op, l as f64, r as f64),
+        TokenType::StarStar => u32::try_from(r).ok().and_then(|r| l.checked_pow(r)),
+        _ => unreachable!("not an arithmetic operator"),
+    };
+    match result {
+        Some(result) => Ok(Object::Integer(result)),
+        None => bigints(op, BigInt::from_i64(l), BigInt::from_i64(r)),
+    }
+}
+
+fn bigints(op: TokenType, l: BigInt, r: BigInt) -> Result<Object, &'static str> {
+    Ok(big(match op {
+        TokenType::Plus => l.add(&r),
+        TokenType::Minus => l.sub(&r),
+        TokenType::Star => l.mul(&r),
+        TokenType::Slash => return floats(op, l.to_f64(), r.to_f64()),
+        TokenType::Percent => l.div_rem(&r).ok_or("attempted to divide by 0")?.1,
+        TokenType::TildeSlash => l.div_rem(&r).ok_or("attempted to divide by 0")?.0,
+        TokenType::StarStar if r.is_negative() => return floats(op, l.to_f64(), r.to_f64()),
+        TokenType::StarStar => l.pow(exponent(&r, l.bits())?),
+        _ => unreachable!("not an arithmetic operator"),
+    }))
+}
+
+fn decimals(op: TokenType, l: Decimal, r: Decimal, rounding: Rounding) -> Result<Object, &'static str> {
+    Ok(Object::Decimal(match op {
+        TokenType::Plus => l.add(&r),
+        TokenType::Minus => l.sub(&r),
+        TokenType::Star => l.mul(&r),
+        TokenType::Slash => l.div(&r, rounding).ok_or("attempted to divide by 0")?,
+        TokenType::Percent => l.div_rem(&r).ok_or("attempted to divide by 0")?.1,
+        TokenType::TildeSlash => Decimal::from_integer(l.div_rem(&r).ok_or("attempted to divide by 0")?.0),
+        TokenType::StarStar => {
+            let r = r.div_rem(&Decimal::from_integer(BigInt::from_i64(1))).filter(|(_, fraction)| fraction.is_zero());
+            match r {
+                Some((r, _)) if !r.is_negative() => {
+                    l.pow(exponent(&r, l.bytes() * 8)?).ok_or("Result of '**' is too large")?
+                }
+                _ => return Err("A decimal can only be raised to a whole, non-negative power"),
+            }
+        }
+        _ => unreachable!("not an arithmetic operator"),
+    }))
+}
+
+fn floats(op: TokenType, l: f64, r: f64) -> Result<Object, &'static str> {
+    Ok(Object::Number(match op {
+        TokenType::Plus => l + r,
+        TokenType::Minus => l - r,
+        TokenType::Star => l * r,
+        // `%` and `~/` truncate like C's, so l == (l ~/ r) * r + l % r
+        TokenType::Slash | TokenType::Percent | TokenType::TildeSlash if r == 0.0 => return Err("attempted to divide by 0"),
+        TokenType::Slash => l / r,
+        TokenType::Percent => l % r,
+        TokenType::TildeSlash => (l / r).trunc(),
+        TokenType::StarStar => l.powf(r),
+        _ => unreachable!("not an arithmetic operator"),
+    }))
+}
+
+fn exponent(r: &BigInt, base_bits: usize) -> Result<u32, &'static str> {
+    // refuses powers whose result would be too large to work out
+    r.to_i64()
+        .and_then(|r| u32::try_from(r).ok())
+        .filter(|&r| base_bits.saturating_mul(r as usize) <= MAX_POWER_BITS)
+        .ok_or("Result of '**' is too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(op: TokenType, left: Object, right: Object) -> Result<Object, &'static str> {
+        arithmetic(op, &left, &right, Rounding::default())
+    }
+
+    #[test]
+    fn remainder_and_integer_division_truncate() {
+        assert_eq!(apply(TokenType::Percent, Object::Integer(-7), Object::Integer(3)), Ok(Object::Integer(-1)));
+        assert_eq!(apply(TokenType::TildeSlash, Object::Integer(-7), Object::Integer(2)), Ok(Object::Integer(-3)));
+        assert_eq!(apply(TokenType::Percent, Object::Number(7.5), Object::Integer(2)), Ok(Object::Number(1.5)));
+        assert_eq!(apply(TokenType::TildeSlash, Object::Number(7.0), Object::Integer(2)), Ok(Object::Number(3.0)));
+        assert_eq!(apply(TokenType::Percent, Object::Integer(1), Object::Integer(0)), Err("attempted to divide by 0"));
+    }
+
+    #[test]
+    fn powers_stay_whole_until_the_exponent_is_negative() {
+        assert_eq!(apply(TokenType::StarStar, Object::Integer(2), Object::Integer(10)), Ok(Object::Integer(1024)));
+        assert_eq!(apply(TokenType::StarStar, Object::Integer(2), Object::Integer(-1)), Ok(Object::Number(0.5)));
+        assert_eq!(apply(TokenType::StarStar, Object::Number(4.0), Object::Number(0.5)), Ok(Object::Number(2.0)));
+    }
+
+    #[test]
+    fn integers_stay_integers_until_mixed_with_a_float() {
+        assert_eq!(apply(TokenType::Plus, Object::Integer(1), Object::Integer(2)), Ok(Object::Integer(3)));
+        assert_eq!(apply(TokenType::Plus, Object::Integer(1), Object::Number(2.0)), Ok(Object::Number(3.0)));
+        assert_eq!(apply(TokenType::Slash, Object::Integer(7), Object::Integer(2)), Ok(Object::Number(3.5)));
+        let exact = Object::Integer(9_007_199_254_740_993); // one past what a float holds exactly
+        assert_eq!(apply(TokenType::Minus, exact, Object::Integer(1)), Ok(Object::Integer(9_007_199_254_740_992)));
+    }
+
+    #[test]
+    fn integers_that_overflow_become_big_and_shrink_back() {
+        let sum = apply(TokenType::Plus, Object::Integer(i64::MAX), Object::Integer(1)).unwrap();
+        assert_eq!(sum, Object::BigInt(BigInt::parse("9223372036854775808").unwrap()));
+        assert_eq!(apply(TokenType::Minus, sum, Object::Integer(1)), Ok(Object::Integer(i64::MAX)));
+        assert_eq!(negate(&Object::Integer(i64::MIN)), Some(Object::BigInt(BigInt::parse("9223372036854775808").unwrap())));
+    }
+
+    #[test]
+    fn decimals_mix_with_integers_but_not_floats() {
+        let tenth = Object::Decimal(Decimal::parse("0.1").unwrap());
+        let sum = apply(TokenType::Plus, tenth.clone(), Object::Integer(1)).unwrap();
+        assert_eq!(sum, Object::Decimal(Decimal::parse("1.1").unwrap()));
+        assert_eq!(apply(TokenType::Plus, tenth, Object::Number(0.5)),
+            Err("Can't mix decimals and floats, convert one with decimal() or float()"));
+    }
+
+    #[test]
+    fn numbers_compare_by_value_whatever_their_kind() {
+        let decimal = |text| Object::Decimal(Decimal::parse(text).unwrap());
+        assert_eq!(compare(&decimal("1.10"), &decimal("1.1")), Some(Some(Ordering::Equal)));
+        assert_eq!(compare(&decimal("1.5"), &Object::Integer(2)), Some(Some(Ordering::Less)));
+        assert_eq!(compare(&Object::Integer(1), &Object::Number(1.0)), Some(Some(Ordering::Equal)));
+        assert_eq!(compare(&Object::Integer(1), &Object::String("1".to_string())), None);
+    }
+
+    #[test]
+    fn bitwise_operators_act_on_twos_complement() {
+        let int = Object::Integer;
+        assert_eq!(bitwise(TokenType::Ampersand, &int(12), &int(10)), Ok(int(8)));
+        assert_eq!(bitwise(TokenType::Pipe, &int(12), &int(10)), Ok(int(14)));
+        assert_eq!(bitwise(TokenType::Caret, &int(12), &int(10)), Ok(int(6)));
+        assert_eq!(bitwise(TokenType::Ampersand, &int(-1), &int(255)), Ok(int(255)));
+        assert_eq!(bitwise(TokenType::GreaterGreater, &int(-5), &int(1)), Ok(int(-3)));
+        assert_eq!(bitwise(TokenType::GreaterGreater, &int(-5), &int(100)), Ok(int(-1)));
+        assert_eq!(complement(&int(5)), Ok(int(-6)));
+        assert_eq!(bitwise(TokenType::Pipe, &Object::Number(4.0), &int(1)), Ok(int(5)));
+    }
+
+    #[test]
+    fn shifting_left_grows_into_a_big_integer() {
+        let shifted = bitwise(TokenType::LessLess, &Object::Integer(1), &Object::Integer(64)).unwrap();
+        assert_eq!(shifted, Object::BigInt(BigInt::parse("18446744073709551616").unwrap()));
+        assert_eq!(bitwise(TokenType::GreaterGreater, &shifted, &Object::Integer(64)), Ok(Object::Integer(1)));
+        assert_eq!(complement(&Object::Integer(i64::MIN)), Ok(Object::Integer(i64::MAX)));
+        assert_eq!(complement(&shifted), Ok(Object::BigInt(BigInt::parse("-18446744073709551617").unwrap())));
+    }
+
+    #[test]
+    fn bitwise_operators_refuse_fractions_and_negative_shifts() {
+        assert_eq!(bitwise(TokenType::LessLess, &Object::Integer(1), &Object::Integer(-1)), Err("Can't shift by a negative amount"));
+        assert_eq!(bitwise(TokenType::Ampersand, &Object::Number(1.5), &Object::Integer(1)),
+            Err("Operands of bitwise operators must be whole numbers"));
+        assert_eq!(complement(&Object::Decimal(Decimal::parse("0.5").unwrap())),
+            Err("Operands of bitwise operators must be whole numbers"));
+        assert_eq!(bitwise(TokenType::Pipe, &Object::Integer(1), &Object::Null), Err("Operand must be a number"));
+    }
+}
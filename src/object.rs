@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::callable::LoxCallable;
+use crate::decimal::Decimal;
 
 //an enume to emulate Java's Object type
 #[derive(Debug, Clone, PartialEq)]
@@ -5,5 +12,78 @@ pub enum Object {
     Boolean(bool),
     Null,
     Number(f64),
-    String(String)
+    Integer(i64),
+    BigInt(BigInt),
+    Decimal(Decimal),
+    String(String),
+    Callable(Rc<dyn LoxCallable>),
+    List(Rc<Vec<Object>>),
+    Module(Rc<Module>),
+}
+
+impl Object {
+    pub fn as_float(&self) -> Option<f64> {
+        // any number as a float, for arithmetic that mixes the two kinds
+        match self {
+            Object::Number(n) => Some(*n),
+            Object::Integer(i) => Some(*i as f64),
+            Object::BigInt(b) => Some(b.to_f64()),
+            Object::Decimal(d) => Some(d.to_f64()),
+            _ => None,
+        }
+    }
+}
+
+pub fn format_float(n: f64) -> String {
+    // whole floats keep a `.0` so they can't be mistaken for integers, 2.0 is not 2
+    let s = n.to_string();
+    if n.is_finite() && !s.contains('.') {
+        return format!("{}.0", s)
+    }
+    s
+}
+
+// A namespace of values, such as `io`, whose members are reached with `module.name`.
+pub struct Module {
+    pub name: &'static str,
+    members: HashMap<String, Object>,
+}
+
+impl Module {
+    pub fn new(name: &'static str, members: Vec<(String, Object)>) -> Self {
+        Self { name, members: members.into_iter().collect() }
+    }
+
+    pub fn define(&mut self, name: &str, value: Object) {
+        self.members.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.members.get(name).cloned()
+    }
+}
+
+// like functions, a module is only ever equal to itself
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_floats_keep_their_point() {
+        assert_eq!(format_float(2.0), "2.0");
+        assert_eq!(format_float(-0.5), "-0.5");
+        assert_eq!(format_float(f64::INFINITY), "inf");
+    }
 }
@@ -1,23 +1,37 @@
 use std::cmp::{min};
+use std::collections::HashSet;
+use std::rc::Rc;
 
 use crate::token::{Literal, Token};
-use crate::token_type::{self, TokenType};
-use crate::expr::{Assign, Binary, Expr, Grouping, Unary, Variable as VariableExpr, Logical};
+use crate::token_type::TokenType;
+use crate::expr::{Assign, Binary, Expr, Grouping, Unary, Variable as VariableExpr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda, Param, Argument};
 use crate::lox_error;
-use crate::stmt::{Expression, Print, Stmt, Variable, Block, If, While};
+use crate::stmt::{Expression, Print, Stmt, Variable, Block, If, While, ForLoop, Test, Function, Return};
+
+// How deeply statements and expressions can nest. The parser recurses once per level, and so does
+// everything that walks the tree afterwards, so a script nested deeper than this is refused rather
+// than left to overflow the stack.
+const MAX_NESTING: usize = 256;
 
 pub struct Parser {
     tokens: Vec<Token>,
-    current: usize
+    current: usize,
+    functions: usize, // how many function bodies the parser is inside, `return` needs at least one
+    arrows: HashSet<usize>, // positions of the '(' tokens that start an arrow function
+    depth: usize, // how many levels of nesting the parser is inside, see nested
 }
 
 pub struct ParserError;
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self{
+        let arrows = arrow_parens(&tokens);
         Self{
-            tokens: tokens,
-            current: 0
+            tokens,
+            current: 0,
+            functions: 0,
+            arrows,
+            depth: 0,
         }
     }
 
@@ -28,19 +42,35 @@ impl Parser {
             statements.push(self.declaration()?);
         }
 
-        return Some(statements);
+        Some(statements)
+    }
+
+    pub fn parse_expression(&mut self) -> Option<Expr> {
+        // a lone expression, for places like the debugger's `print` command
+        let expr = self.expression().ok()?;
+        if !self._at_end() {
+            self._error(self._peek(), "Expected end of expression");
+            return None;
+        }
+        Some(expr)
     }
 
     fn declaration(&mut self) -> Option<Stmt> {
         let token_type = [TokenType::Var];
-        let statement = match self._match(&token_type) {
-            true => self.var_statement(),
-            false => self.statement()
+        let statement = if self._match(&token_type) {
+            self.var_statement()
+        } else if self._check_function() {
+            self._advance();
+            self.function_declaration()
+        } else if self._check_test() {
+            self.test_declaration()
+        } else {
+            self.statement()
         };
 
         match statement {
             Ok(stm) => Some(stm),
-            Err(stm) => {
+            Err(_stm) => {
                 self._synchronize();
                 None
             }
@@ -60,7 +90,9 @@ impl Parser {
 
         let token_type = [TokenType::LeftBrace];
         if self._match(&token_type) {
-            return Ok(Stmt::Block(Block{statements: self.block()?}))
+            let line = self._previous().line;
+            let statements = self.nested(Parser::block)?;
+            return Ok(Stmt::Block(Block{statements, line, end_line: self._previous().line}))
         }
 
         let token_type = [TokenType::While];
@@ -73,7 +105,12 @@ impl Parser {
             return self.for_statement()
         }
 
-        return self.expression_statement()
+        let token_type = [TokenType::Return];
+        if self._match(&token_type) {
+            return self.return_statement()
+        }
+
+        self.expression_statement()
     }
 
 
@@ -87,44 +124,110 @@ impl Parser {
             false => Ok(Expr::Literal(Literal::Nil)) // var a -> means var a = None;
         };
         self._consume(&TokenType::Semicolon, "Expected ';' at the end of statement")?;
-        return Ok(Stmt::Variable(Variable {name: name, initializer: expr?}));
+        Ok(Stmt::Variable(Variable {name, initializer: expr?}))
+    }
+
+    fn function_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self._consume(&TokenType::Identifier, "Expected function name")?.clone();
+        self._consume(&TokenType::LeftParen, "Expected '(' after function name")?;
+        let lambda = self.function()?;
+        Ok(Stmt::Function(Function { name, lambda }))
+    }
+
+    fn function(&mut self) -> Result<Lambda, ParserError> {
+        // the parameters, from just after the '(', and the body
+        let params = self.parameters()?;
+        self._consume(&TokenType::LeftBrace, "Expected '{' before function body")?;
+        self.functions += 1;
+        let body = self.nested(Parser::block);
+        self.functions -= 1;
+        Ok(Lambda { params: Rc::new(params), body: Rc::new(body?), arrow: false, end_line: self._previous().line })
+    }
+
+    fn parameters(&mut self) -> Result<Vec<Param>, ParserError> {
+        // `a, b = default, ...rest`, defaults are only parsed here, they're evaluated on each call
+        let mut params: Vec<Param> = Vec::new();
+        if !self._check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    self._error(self._peek(), "Can't have more than 255 parameters");
+                }
+                if params.last().is_some_and(|p| p.rest) {
+                    self._error(self._peek(), "A rest parameter must be the last one");
+                }
+                let rest = self._match(&[TokenType::Ellipsis]);
+                let name = self._consume(&TokenType::Identifier, "Expected parameter name")?.clone();
+                let mut default = None;
+                if self._match(&[TokenType::Equal]) {
+                    if rest {
+                        self._error(self._previous(), "A rest parameter can't have a default value");
+                    }
+                    default = Some(self.nested(Parser::assignment)?);
+                }
+                // otherwise a call that leaves out the defaulted one couldn't fill in the later one
+                if default.is_none() && !rest && params.iter().any(|p| p.default.is_some()) {
+                    self._error(&name, "A parameter without a default can't follow one with a default");
+                }
+                params.push(Param { name, default, rest });
+                if !self._match(&[TokenType::Comma]) {
+                    break
+                }
+            }
+        }
+        self._consume(&TokenType::RightParen, "Expected ')' after parameters")?;
+        Ok(params)
+    }
+
+    fn test_declaration(&mut self) -> Result<Stmt, ParserError> {
+        // test "name" { ... }
+        let line = self._advance().line;
+        let name = self._advance().clone();
+        self._consume(&TokenType::LeftBrace, "Expected '{' before the test body")?;
+        let body = self.nested(Parser::block)?;
+        Ok(Stmt::Test(Test { name, body, line, end_line: self._previous().line }))
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self._previous().line;
         self._consume(&TokenType::LeftParen, "Expected a '(' after 'if'")?;
         let condition = self.expression()?;
         self._consume(&TokenType::RightParen, "Expected a ')' end of 'if' expression")?;
 
-        let then_stmt = Box::new(self.statement()?);
+        let then_stmt = Box::new(self.nested(Parser::statement)?);
         let mut else_stmt = None;
         let token_type = [TokenType::Else];
         if self._match(&token_type) {
-            else_stmt = Some(Box::new(self.statement()?));
+            else_stmt = Some(Box::new(self.nested(Parser::statement)?));
         }
 
-        return Ok(Stmt::If(If{
-            condition: condition,
+        Ok(Stmt::If(If{
+            condition,
             then_branch: then_stmt,
-            else_branch: else_stmt
+            else_branch: else_stmt,
+            line
         }))
 
     }
 
     fn while_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self._previous().line;
         self._consume(&TokenType::LeftParen, "Expected a '(' after 'while'")?;
         let condition = self.expression()?;
         self._consume(&TokenType::RightParen, "Expected a ')' end of 'while' expression")?;
 
-        let body = Box::new(self.statement()?);
+        let body = Box::new(self.nested(Parser::statement)?);
 
-        return Ok(Stmt::While(While{
-            condition: condition,
-            body: body,
+        Ok(Stmt::While(While{
+            condition,
+            body,
+            line,
+            for_loop: None,
         }))
     }
 
     fn for_statement(&mut self) -> Result<Stmt, ParserError> {
         // there is no for statement trait, we just desugar it into a while loop
+        let line = self._previous().line;
 
         self._consume(&TokenType::LeftParen, "Expected a '(' after 'for'")?;
         let initializer: Option<Stmt>;
@@ -150,12 +253,20 @@ impl Parser {
         self._consume(&semicolon[0], "Expected a ';' after conditon in 'for'")?;
 
         let mut increment: Option<Expr> = None;
-        if !self._check(&semicolon[0]){
+        if !self._check(&TokenType::RightParen){
             increment = Some(self.expression()?);
         }
 
-        self._consume(&&TokenType::RightParen, "Expected a ')' end of 'for'")?;
-        let mut body = self.statement()?;
+        self._consume(&TokenType::RightParen , "Expected a ')' end of 'for'")?;
+        let mut body = self.nested(Parser::statement)?;
+        let end_line = self._previous().line;
+
+        // remember what the user wrote so the desugared loop can be told apart from a hand written one
+        let for_loop = ForLoop {
+            initializer: initializer.is_some(),
+            condition: condition.is_some(),
+            increment: increment.is_some(),
+        };
 
         if let Some(e) = increment {
             // if an increment exists, then it should be executed after the body every loop
@@ -164,15 +275,17 @@ impl Parser {
             body = Stmt::Block(Block {
                 statements: vec![
                     body,
-                    Stmt::Expression(Expression { expression: e })
-                ]
+                    Stmt::Expression(Expression { expression: e, line })
+                ],
+                line,
+                end_line
             });
         }
 
         // if no condition, then explicity set it to true
-        let condition = condition.unwrap_or_else(|| Expr::Literal(Literal::Bool(true)));
+        let condition = condition.unwrap_or(Expr::Literal(Literal::Bool(true)));
 
-        body = Stmt::While(While { condition: condition, body: Box::new(body) });
+        body = Stmt::While(While { condition, body: Box::new(body), line, for_loop: Some(for_loop) });
 
         // finally, jam the initializer, if it exists, to the top so it runs once before the while loop
         if let Some(e) = initializer {
@@ -180,23 +293,41 @@ impl Parser {
                 statements: vec![
                      e,
                     body
-                ]
+                ],
+                line,
+                end_line
             });
         }
 
         Ok(body)
     }
 
+    fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self._previous().clone();
+        if self.functions == 0 {
+            // keep parsing, the rest of the statement is fine
+            self._error(&keyword, "Can't return from top-level code");
+        }
+        let value = match self._check(&TokenType::Semicolon) {
+            true => None,
+            false => Some(self.expression()?),
+        };
+        self._consume(&TokenType::Semicolon, "Expected ';' after return value")?;
+        Ok(Stmt::Return(Return { keyword, value }))
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self._previous().line;
         let expr = self.expression();
         self._consume(&TokenType::Semicolon, "Expected ';' at the end of statement")?;
-        return Ok(Stmt::Print(Print {expression: expr?}));
+        Ok(Stmt::Print(Print {expression: expr?, line}))
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self._peek().line;
         let expr = self.expression();
         self._consume(&TokenType::Semicolon, "Expected ';' at the end of statement")?;
-        return Ok(Stmt::Expression(Expression {expression: expr?}));
+        Ok(Stmt::Expression(Expression {expression: expr?, line}))
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>, ParserError> {
@@ -212,11 +343,29 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Result<Expr, ParserError>{
-        self.assignment()
+        self.sequence()
+    }
+
+    fn sequence(&mut self) -> Result<Expr, ParserError> {
+        // challenge question ch6. Comma has lowest precedence in C according to stackoverflow
+        // https://stackoverflow.com/questions/54142/how-does-the-comma-operator-work-and-what-precedence-does-it-have
+        // Places where commas separate things, like call arguments, parse assignments instead.
+        self.chain(&[TokenType::Comma], Parser::assignment, |left, _, right| {
+            Expr::Sequence(Sequence { left: Box::new(left), right: Box::new(right) })
+        })
     }
 
     fn assignment(&mut self) ->  Result<Expr, ParserError> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
+        let compound = [TokenType::PlusEqual, TokenType::MinusEqual, TokenType::StarEqual,
+            TokenType::SlashEqual, TokenType::PercentEqual];
+        if self._match(&compound) {
+            let op = self._previous().clone();
+            let value = self.nested(Parser::assignment)?;
+            let name = self.update_target(expr, &op)?;
+            return Ok(Expr::Update(Update { name, op, value: Some(Box::new(value)), prefix: true }))
+        }
+
         let token_types = [TokenType::Equal];
 
         if !self._match(&token_types) {
@@ -226,9 +375,9 @@ impl Parser {
         match expr {
             Expr::Variable(var) => {
                 // a = b = 2 for example
-                let value = self.assignment()?;
+                let value = self.nested(Parser::assignment)?;
                 let name = var.name.clone();
-                Ok(Expr::Assign(Assign {name: name, value: Box::new(value)}))
+                Ok(Expr::Assign(Assign {name, value: Box::new(value)}))
             },
             _ => {
                 let prev = self._previous();
@@ -237,42 +386,77 @@ impl Parser {
         }
     }
 
-    fn or(&mut self) -> Result<Expr, ParserError> {
-        let token_types = [TokenType::Or];
-        let mut expr = self.and()?;
-        while self._match(&token_types){
-            let operator = self._previous().clone();
-            let right = self.and()?;
-            expr = Expr::Logical(Logical{condition: operator, left: Box::new(expr), right: Box::new(right)});
+    fn update_target(&self, target: Expr, op: &Token) -> Result<Token, ParserError> {
+        // what `+=`, `++` and `--` can change, only variables so far
+        match target {
+            Expr::Variable(var) => Ok(var.name),
+            _ => Err(self._error(op, "Invalid assignment target")),
         }
-        Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, ParserError> {
-        let token_types = [TokenType::And];
-        let mut expr = self.comma()?;
-        while self._match(&token_types){
-            let operator = self._previous().clone();
-            let right = self.comma()?;
-            expr = Expr::Logical(Logical{condition: operator, left: Box::new(expr), right: Box::new(right)});
+    fn conditional(&mut self) -> Result<Expr, ParserError> {
+        // C's grammar: anything at all between `?` and `:`, commas included since the two tokens
+        // bracket it, and another conditional after the `:` so a ? b : c ? d : e groups to the right
+        let condition = self.or()?;
+        if !self._match(&[TokenType::Question]) {
+            return Ok(condition)
         }
-        Ok(expr)
+        let question = self._previous().clone();
+        let then_branch = self.nested(Parser::expression)?;
+        self._consume(&TokenType::Colon, "Expected ':' after the then branch of a conditional")?;
+        let else_branch = self.nested(Parser::conditional)?;
+        Ok(Expr::Conditional(Conditional {
+            condition: Box::new(condition),
+            question,
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        }))
     }
 
-    fn comma(&mut self) -> Result<Expr, ParserError> {
-        // challenge question ch6. Comma has lowest precedence in C according to stackoverflow
-        // https://stackoverflow.com/questions/54142/how-does-the-comma-operator-work-and-what-precedence-does-it-have
-        let token_types = [TokenType::Comma];
-        self._left_recurse_binary(&token_types, Parser::equality)
+    fn or(&mut self) -> Result<Expr, ParserError> {
+        let token_types = [TokenType::Or];
+        self.chain(&token_types, Parser::and, |left, operator, right| {
+            Expr::Logical(Logical{condition: operator, left: Box::new(left), right: Box::new(right)})
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, ParserError> {
+        let token_types = [TokenType::And];
+        self.chain(&token_types, Parser::equality, |left, operator, right| {
+            Expr::Logical(Logical{condition: operator, left: Box::new(left), right: Box::new(right)})
+        })
     }
 
     fn equality(&mut self) -> Result<Expr, ParserError> {
         let token_types = [TokenType::BangEqual, TokenType::EqualEqual];
+        self._left_recurse_binary(&token_types, Parser::bit_or)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ParserError> {
+        // `|`, `^` and `&` go in C's order among themselves but bind tighter than `==`, unlike in C,
+        // so flags & MASK == 0 tests the masked bits rather than and-ing flags with a boolean
+        let token_types = [TokenType::Pipe];
+        self._left_recurse_binary(&token_types, Parser::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParserError> {
+        let token_types = [TokenType::Caret];
+        self._left_recurse_binary(&token_types, Parser::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParserError> {
+        let token_types = [TokenType::Ampersand];
         self._left_recurse_binary(&token_types, Parser::comparison)
     }
 
     fn comparison(&mut self) -> Result<Expr, ParserError> {
         let token_types = [TokenType::Greater, TokenType::GreaterEqual, TokenType::LessEqual, TokenType::Less];
+        self._left_recurse_binary(&token_types, Parser::shift)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParserError> {
+        // between comparison and term like in C, 1 << n < limit compares the shifted value
+        let token_types = [TokenType::LessLess, TokenType::GreaterGreater];
         self._left_recurse_binary(&token_types, Parser::term)
     }
 
@@ -282,25 +466,93 @@ impl Parser {
     }
 
     fn factor(&mut self) -> Result<Expr, ParserError> {
-        let token_types = [TokenType::Star, TokenType::Slash];
+        let token_types = [TokenType::Star, TokenType::Slash, TokenType::Percent, TokenType::TildeSlash];
         self._left_recurse_binary(&token_types, Parser::unary)
     }
 
     fn unary(&mut self) -> Result<Expr, ParserError> {
-        let token_types = [TokenType::Bang, TokenType::Minus];
-        let expr;
-        if self._match(&token_types){
+        if self._match(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let op = self._previous().clone();
+            let target = self.nested(Parser::unary)?;
+            let name = self.update_target(target, &op)?;
+            return Ok(Expr::Update(Update { name, op, value: None, prefix: true }))
+        }
+        let token_types = [TokenType::Bang, TokenType::Minus, TokenType::Tilde];
+        let expr = if self._match(&token_types){
             let operator = self._previous().clone();
-            let right = self.unary()?;
-            expr = Expr::Unary(Unary { op:operator, right: Box::new(right) });
+            let right = self.nested(Parser::unary)?;
+            Expr::Unary(Unary { op:operator, right: Box::new(right) })
         } else {
-            expr = self.primary()?;
-        }
+            self.power()?
+        };
         Ok(expr)
     }
 
-    // if i were to support postfix (e.g a++) i'd add it here as a method and
-    // has its precedence right before primary
+    fn power(&mut self) -> Result<Expr, ParserError> {
+        // `**` binds tighter than a unary operator on its left, so -2 ** 2 is -(2 ** 2), and
+        // groups to the right, 2 ** 3 ** 2 is 2 ** (3 ** 2)
+        let expr = self.postfix()?;
+        if !self._match(&[TokenType::StarStar]) {
+            return Ok(expr)
+        }
+        let operator = self._previous().clone();
+        let right = self.nested(Parser::unary)?;
+        Ok(Expr::Binary(Binary { op: operator, left: Box::new(expr), right: Box::new(right) }))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ParserError> {
+        let expr = self.call()?;
+        if !self._match(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            return Ok(expr)
+        }
+        let op = self._previous().clone();
+        let name = self.update_target(expr, &op)?;
+        Ok(Expr::Update(Update { name, op, value: None, prefix: false }))
+    }
+
+    fn call(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self._match(&[TokenType::LeftParen]) {
+                let arguments = self.nested(Parser::arguments);
+                let paren = self._consume(&TokenType::RightParen, "Expected ')' after arguments")?.clone();
+                expr = Expr::Call(Call { callee: Box::new(expr), paren, arguments: arguments? });
+            } else if self._match(&[TokenType::Dot]) {
+                let name = self._consume(&TokenType::Identifier, "Expected property name after '.'")?.clone();
+                expr = Expr::Get(Get { object: Box::new(expr), name });
+            } else if self._match(&[TokenType::LeftBracket]) {
+                let index = self.nested(Parser::expression)?;
+                let bracket = self._consume(&TokenType::RightBracket, "Expected ']' after index")?.clone();
+                expr = Expr::Index(Index { object: Box::new(expr), bracket, index: Box::new(index) });
+            } else {
+                return Ok(expr)
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Argument>, ParserError> {
+        let mut arguments: Vec<Argument> = Vec::new();
+        if self._check(&TokenType::RightParen) {
+            return Ok(arguments)
+        }
+        loop {
+            if arguments.len() >= 255 {
+                // keep parsing, the call is still well formed
+                self._error(self._peek(), "Can't have more than 255 arguments");
+            }
+            let mut name = None;
+            if self._check_named_argument() {
+                name = Some(self._advance().clone());
+                self._advance();
+            } else if arguments.iter().any(|a| a.name.is_some()) {
+                self._error(self._peek(), "Positional arguments must come before named ones");
+            }
+            arguments.push(Argument { name, value: self.assignment()? });
+            if !self._match(&[TokenType::Comma]) {
+                return Ok(arguments)
+            }
+        }
+    }
 
     fn primary(&mut self) -> Result<Expr, ParserError> {
         if self._match(&[TokenType::True]){
@@ -320,15 +572,32 @@ impl Parser {
             return Ok(Expr::Literal(literal))
         }
 
+        if self._match(&[TokenType::Fun]){
+            self._consume(&TokenType::LeftParen, "Expected '(' after 'fun'")?;
+            return Ok(Expr::Lambda(self.function()?))
+        }
+
+        if self.check_arrow() {
+            self._advance();
+            let params = self.nested(Parser::parameters)?;
+            let arrow = self._consume(&TokenType::Arrow, "Expected '=>' after parameters")?.clone();
+            // an assignment, so a comma after the value ends the lambda, f((x) => x, 1) has two arguments
+            let value = self.nested(Parser::assignment)?;
+            let body = vec![Stmt::Return(Return { keyword: arrow.clone(), value: Some(value) })];
+            let end_line = self._previous().line;
+            return Ok(Expr::Lambda(Lambda { params: Rc::new(params), body: Rc::new(body), arrow: true, end_line }))
+        }
+
         if self._match(&[TokenType::LeftParen]){
-            let expr = self.expression()?;
+            // inside parentheses commas are operators again, even within call arguments
+            let expr = self.nested(Parser::expression)?;
             self._consume(&TokenType::RightParen, "expected right paranthesis")?;
             return Ok(Expr::Grouping(Grouping { expression: Box::new(expr) }))
         }
 
         if self._match(&[TokenType::Identifier]){
             let name = self._previous().clone();
-            return Ok(Expr::Variable(VariableExpr { name: name}))
+            return Ok(Expr::Variable(VariableExpr { name}))
         }
         /* binary error productiond. If we find a binary operator here it means that the
         binary expression method's left operand does not exist. e.g => (>= 2).
@@ -337,37 +606,81 @@ impl Parser {
         */
         if self._match(&[TokenType::BangEqual, TokenType::Equal]){
             self._error(self._peek(), "Missing Left Hand Operand");
-            return self.equality();
+            return self.nested(Parser::equality);
+        }
+        if self._match(&[TokenType::Pipe, TokenType::Caret, TokenType::Ampersand]){
+            self._error(self._peek(), "Missing Left Hand Operand");
+            return self.nested(Parser::bit_or);
         }
         if self._match(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]){
             self._error(self._peek(), "Missing Left Hand Operand");
-            return self.comparison();
+            return self.nested(Parser::comparison);
+        }
+        if self._match(&[TokenType::LessLess, TokenType::GreaterGreater]){
+            self._error(self._peek(), "Missing Left Hand Operand");
+            return self.nested(Parser::shift);
         }
         if self._match(&[TokenType::Plus]){
             self._error(self._peek(), "Missing Left Hand Operand");
-            return self.term();
+            return self.nested(Parser::term);
+        }
+        if self._match(&[TokenType::Star, TokenType::Slash, TokenType::Percent, TokenType::TildeSlash]){
+            self._error(self._peek(), "Missing Left Hand Operand");
+            return self.nested(Parser::factor);
         }
-        if self._match(&[TokenType::Star, TokenType::Slash]){
+        if self._match(&[TokenType::StarStar]){
             self._error(self._peek(), "Missing Left Hand Operand");
-            return self.factor();
+            return self.nested(Parser::unary);
         }
 
         Err(self._error(self._peek(), "expected expression"))
     }
 
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, ParserError>) -> Result<T, ParserError> {
+        // every place the parser recurses goes through here, so the depth counts levels of nesting
+        if self.depth == MAX_NESTING {
+            return Err(self._error(self._peek(), "Too much nesting"))
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn _left_recurse_binary<F>(&mut self, token_types: &[TokenType], method: F) -> Result<Expr, ParserError>
     where F: Fn(&mut Self) -> Result<Expr, ParserError>
     {
         // This method takes in a method as an argument (which here is the function of next precedence)
         // which it then passes the mutable self to it to continue it's recursing journey.
         // Finally it constructs the expression and returns it
-        let mut expr = method(self)?;
-        while self._match(token_types){
+        self.chain(token_types, method, |left, operator, right| {
+            Expr::Binary(Binary{op: operator, left: Box::new(left), right: Box::new(right)})
+        })
+    }
+
+    fn chain<F>(&mut self, token_types: &[TokenType], operand: F, combine: fn(Expr, Token, Expr) -> Expr) -> Result<Expr, ParserError>
+    where F: Fn(&mut Self) -> Result<Expr, ParserError>
+    {
+        // operands separated by any of the operators, grouped to the left. Each operator puts the
+        // ones before it a level deeper in the tree, so a long chain counts as nesting too
+        let mut expr = operand(self)?;
+        let depth = self.depth;
+        let result = loop {
+            if !self._match(token_types) {
+                break Ok(expr)
+            }
             let operator = self._previous().clone();
-            let right = method(self)?;
-            expr = Expr::Binary(Binary{op: operator, left: Box::new(expr), right: Box::new(right)});
-        }
-        Ok(expr)
+            if self.depth == MAX_NESTING {
+                break Err(self._error(&operator, "Too much nesting"))
+            }
+            self.depth += 1;
+            match operand(self) {
+                Ok(right) => expr = combine(expr, operator, right),
+                Err(err) => break Err(err),
+            }
+        };
+        self.depth = depth;
+        result
     }
 
     fn _match(&mut self, token_types: &[TokenType]) -> bool {
@@ -383,7 +696,7 @@ impl Parser {
         for _ in 0..lookahead {
             if self._check(token_type){
                 self._advance();
-                advanced = advanced + 1;
+                advanced += 1;
             } else {
                 for _ in 0..advanced {
                     self._retreat();
@@ -392,7 +705,7 @@ impl Parser {
             }
         }
 
-        return true
+        true
     }
 
     fn _check(&self, token_type: &TokenType) -> bool{
@@ -400,7 +713,30 @@ impl Parser {
             return false
         }
 
-        return *token_type == self._peek().kind
+        *token_type == self._peek().kind
+    }
+
+    fn _check_test(&self) -> bool {
+        // `test` is only a keyword when a test name follows it, it's still fine as a variable name
+        let next = self.tokens.get(self.current + 1);
+        self._check(&TokenType::Identifier) && self._peek().lexeme == "test"
+            && next.is_some_and(|t| t.kind == TokenType::String)
+    }
+
+    fn _check_function(&self) -> bool {
+        // `fun` followed by a name declares a function, without one it's a lambda
+        let next = self.tokens.get(self.current + 1);
+        self._check(&TokenType::Fun) && next.is_some_and(|t| t.kind == TokenType::Identifier)
+    }
+
+    fn check_arrow(&self) -> bool {
+        self._check(&TokenType::LeftParen) && self.arrows.contains(&self.current)
+    }
+
+    fn _check_named_argument(&self) -> bool {
+        // `name: value` in a call's arguments
+        let next = self.tokens.get(self.current + 1);
+        self._check(&TokenType::Identifier) && next.is_some_and(|t| t.kind == TokenType::Colon)
     }
 
     fn _peek(&self) -> &Token {
@@ -467,3 +803,101 @@ impl Parser {
     }
 
 }
+
+fn arrow_parens(tokens: &[Token]) -> HashSet<usize> {
+    // a '(' starts an arrow function rather than a grouping when its matching ')' is followed by
+    // `=>`, the parameters in between can have defaults with parentheses of their own. Matching
+    // them all up front keeps nested groupings from being rescanned at every '('
+    let mut open = Vec::new();
+    let mut arrows = HashSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenType::LeftParen => open.push(i),
+            TokenType::RightParen => {
+                if let Some(start) = open.pop() && tokens.get(i + 1).is_some_and(|t| t.kind == TokenType::Arrow) {
+                    arrows.insert(start);
+                }
+            }
+            _ => {}
+        }
+    }
+    arrows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_NESTING;
+    use crate::ast_printer::AstPrinter;
+    use crate::testing::{on_script_thread, parse, run};
+
+    #[test]
+    fn test_is_only_a_keyword_before_a_name() {
+        assert_eq!(run("var test = 1; test = test + 1; print test;"), Ok("2\n".to_string()));
+        assert_eq!(run("test \"never run here\" { print 1; }"), Ok(String::new()));
+    }
+
+    #[test]
+    fn power_binds_tighter_than_unary_minus_and_groups_to_the_right() {
+        assert_eq!(run("print 2 ** 3 ** 2; print -2 ** 2; print 1 + 2 * 3 ** 2; print 7 ~/ 2 * 2 + 7 % 2;"),
+            Ok("512\n-4\n19\n7\n".to_string()));
+    }
+
+    #[test]
+    fn bitwise_operators_bind_tighter_than_equality_and_shifts_than_comparison() {
+        assert_eq!(run("print 6 & 3 == 2; print 1 | 2 ^ 3 & 1; print 1 << 2 + 1; print 1 << 3 > 7; print ~0 - 1;"),
+            Ok("true\n3\n8\ntrue\n-2\n".to_string()));
+    }
+
+    #[test]
+    fn only_variables_can_be_updated() {
+        for (source, message) in [
+            ("1 += 2;", "Error at '+=': Invalid assignment target"),
+            ("var a; (a)++;", "Error at '++': Invalid assignment target"),
+            ("++1;", "Error at '++': Invalid assignment target"),
+        ] {
+            let (statements, errors) = crate::collect_diagnostics(|| parse(source));
+            assert!(statements.is_none(), "{}", source);
+            let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+            assert_eq!(messages, [message], "{}", source);
+        }
+    }
+
+    #[test]
+    fn a_conditional_needs_its_else_branch() {
+        let (statements, errors) = crate::collect_diagnostics(|| parse("print true ? 1;"));
+        assert!(statements.is_none());
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, ["Error at ';': Expected ':' after the then branch of a conditional"]);
+    }
+
+    #[test]
+    fn functions_and_arrows_are_expressions() {
+        let printed = |source| AstPrinter.print_stmts(&parse(source).expect("the script parses"));
+        assert_eq!(printed("var f = fun (x) { print x; };"), ["var f = (lambda (x) { print x })"]);
+        assert_eq!(printed("var g = (x, y = 2) => x * y;"), ["var g = (lambda (x (= y 2)) { return (* x y) })"]);
+        assert_eq!(printed("var h = () => 1;"), ["var h = (lambda () { return 1 })"]);
+        // the comma after an arrow's value separates arguments rather than joining a sequence
+        assert_eq!(printed("f((x) => x, 1);"), ["(call f (lambda (x) { return x }) 1)"]);
+        assert_eq!(printed("f((x) => (x, 1));"), ["(call f (lambda (x) { return (group (, x 1)) }))"]);
+    }
+
+    #[test]
+    fn nesting_too_deep_is_an_error_rather_than_a_stack_overflow() {
+        on_script_thread(|| {
+            let deep = 30_000;
+            for (source, message) in [
+                (format!("print {}1{};", "(".repeat(deep), ")".repeat(deep)), "Error at '(': Too much nesting"),
+                (format!("print {}true;", "!".repeat(deep)), "Error at '!': Too much nesting"),
+                (format!("{}{}", "{".repeat(deep), "}".repeat(deep)), "Error at '{': Too much nesting"),
+                (format!("{}print 1;", "if (true) ".repeat(deep)), "Error at 'if': Too much nesting"),
+                (format!("print 1{};", " + 1".repeat(deep)), "Error at '+': Too much nesting"),
+            ] {
+                let (statements, errors) = crate::collect_diagnostics(|| parse(&source));
+                assert!(statements.is_none());
+                assert_eq!(errors[0].message, message);
+            }
+            let limit = format!("print {}1{};", "(".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+            assert_eq!(run(&limit), Ok("1\n".to_string()));
+        });
+    }
+}
@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use crate::expr::Expr;
+use crate::interpreter::{Hook, Interpreter, RuntimeError};
+use crate::stmt::Stmt;
+
+const SCRIPT: &str = "<script>"; // the top level of the file, outside of any function
+
+#[derive(Default)]
+struct Stats {
+    hits: usize,
+    exprs: usize, // expressions evaluated, a rough measure of the work done between statements
+    total: Duration,
+    own: Duration, // time not spent in nested statements
+}
+
+struct Frame {
+    line: usize,
+    start: Instant,
+    children: Duration,
+}
+
+struct Call {
+    name: String,
+    start: Instant,
+    depth: usize, // how many statements were running when it was called
+}
+
+// Times every statement for `--profile`. Time is attributed to the line a statement starts on and
+// to the function running it, both inclusively and excluding whatever nested statements took, and
+// the nesting is kept as collapsed stacks for flame graphs.
+pub struct Profiler {
+    source: Vec<String>,
+    lines: RefCell<HashMap<usize, Stats>>,
+    functions: RefCell<HashMap<String, Stats>>,
+    stacks: RefCell<HashMap<String, Duration>>,
+    running: RefCell<Vec<Frame>>,
+    calls: RefCell<Vec<Call>>,
+}
+
+impl Hook for Profiler {
+    fn before_stmt(&self, _interpreter: &Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.running.borrow_mut().push(Frame { line: stmt.line(), start: Instant::now(), children: Duration::ZERO });
+        Ok(())
+    }
+
+    fn after_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) {
+        let mut running = self.running.borrow_mut();
+        let Some(frame) = running.pop() else { return };
+        let elapsed = frame.start.elapsed();
+        let own = elapsed.saturating_sub(frame.children);
+        if let Some(parent) = running.last_mut() {
+            parent.children += elapsed;
+        }
+
+        let mut lines = self.lines.borrow_mut();
+        let stats = lines.entry(frame.line).or_default();
+        stats.hits += 1;
+        stats.own += own;
+        // a loop and its body often share a line, count the time once
+        if !running.iter().any(|f| f.line == frame.line) {
+            stats.total += elapsed;
+        }
+
+        // the statement belongs to the innermost function still running, any it called have returned
+        let calls = self.calls.borrow();
+        let name = calls.last().map_or(SCRIPT, |c| c.name.as_str());
+        let mut functions = self.functions.borrow_mut();
+        let function = functions.entry(name.to_string()).or_default();
+        function.own += own;
+        if running.is_empty() {
+            function.total += elapsed;
+        }
+
+        // functions go between the statement that called them and the statements of their body,
+        // desugared `for` loops and blocks sit on the line of their statement, show such a line once
+        let mut stack = SCRIPT.to_string();
+        let mut previous = None;
+        for (depth, f) in running.iter().chain([&frame]).enumerate() {
+            for call in calls.iter().filter(|c| c.depth == depth) {
+                write!(stack, ";{}", call.name).unwrap();
+                previous = None;
+            }
+            if previous != Some(f.line) {
+                write!(stack, ";line {}", f.line).unwrap();
+                previous = Some(f.line);
+            }
+        }
+        *self.stacks.borrow_mut().entry(stack).or_default() += own;
+    }
+
+    fn before_expr(&self, _interpreter: &Interpreter, _expr: &Expr) {
+        if let Some(frame) = self.running.borrow().last() {
+            self.lines.borrow_mut().entry(frame.line).or_default().exprs += 1;
+        }
+    }
+
+    fn enter_function(&self, _interpreter: &Interpreter, name: &str) {
+        let depth = self.running.borrow().len();
+        self.calls.borrow_mut().push(Call { name: name.to_string(), start: Instant::now(), depth });
+    }
+
+    fn exit_function(&self, _interpreter: &Interpreter) {
+        let mut calls = self.calls.borrow_mut();
+        let Some(call) = calls.pop() else { return };
+        let mut functions = self.functions.borrow_mut();
+        let stats = functions.entry(call.name.clone()).or_default();
+        stats.hits += 1;
+        // a recursive call's time is already part of the outermost call's
+        if !calls.iter().any(|c| c.name == call.name) {
+            stats.total += call.start.elapsed();
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new(source: &str) -> Self {
+        let script = Stats { hits: 1, ..Stats::default() };
+        Self {
+            source: source.lines().map(String::from).collect(),
+            lines: RefCell::new(HashMap::new()),
+            functions: RefCell::new(HashMap::from([(SCRIPT.to_string(), script)])),
+            stacks: RefCell::new(HashMap::new()),
+            running: RefCell::new(Vec::new()),
+            calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn report(&self) -> String {
+        // the busiest lines first, by the time spent on the line itself
+        let lines = self.lines.borrow();
+        let mut sorted: Vec<(&usize, &Stats)> = lines.iter().collect();
+        sorted.sort_by(|a, b| b.1.own.cmp(&a.1.own).then(a.0.cmp(b.0)));
+
+        let mut report = String::new();
+        writeln!(report, "{:>6} {:>10} {:>10} {:>12} {:>12}  source", "line", "hits", "exprs", "total ms", "self ms").unwrap();
+        for (line, stats) in sorted {
+            let source = self.source.get(line.wrapping_sub(1)).map(|l| l.trim()).unwrap_or_default();
+            writeln!(
+                report, "{:>6} {:>10} {:>10} {:>12.3} {:>12.3}  {}",
+                line, stats.hits, stats.exprs, millis(stats.total), millis(stats.own), source
+            ).unwrap();
+        }
+
+        let functions = self.functions.borrow();
+        let mut sorted: Vec<(&String, &Stats)> = functions.iter().collect();
+        sorted.sort_by(|a, b| b.1.own.cmp(&a.1.own).then(a.0.cmp(b.0)));
+        writeln!(report).unwrap();
+        writeln!(report, "{:<20} {:>10} {:>12} {:>12}", "function", "calls", "total ms", "self ms").unwrap();
+        for (name, stats) in sorted {
+            writeln!(report, "{:<20} {:>10} {:>12.3} {:>12.3}", name, stats.hits, millis(stats.total), millis(stats.own)).unwrap();
+        }
+        report
+    }
+
+    pub fn collapsed_stacks(&self) -> String {
+        // one `frame;frame;frame count` line per stack, the count being microseconds of self time,
+        // which is what flamegraph.pl and inferno expect
+        let stacks = self.stacks.borrow();
+        let mut sorted: Vec<(&String, &Duration)> = stacks.iter().collect();
+        sorted.sort();
+        sorted.iter().map(|(stack, time)| format!("{} {}\n", stack, time.as_micros())).collect()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::testing::parse;
+
+    const SOURCE: &str = "\
+fun add(a, b) {
+  return a + b;
+}
+for (var i = 0; i < 3; i = i + 1) {
+  add(i, 1);
+}
+";
+
+    fn profile() -> Rc<Profiler> {
+        let profiler = Rc::new(Profiler::new(SOURCE));
+        let (mut interpreter, _printed) = Interpreter::capturing();
+        interpreter.add_hook(profiler.clone());
+        assert!(interpreter.try_interpret(&parse(SOURCE).unwrap()).is_ok());
+        profiler
+    }
+
+    #[test]
+    fn lines_and_functions_count_their_hits() {
+        let profiler = profile();
+        let lines = profiler.lines.borrow();
+        assert_eq!(lines[&2].hits, 3);
+        assert_eq!(lines[&2].exprs, 3 * 3); // the return value and the two operands
+        assert_eq!(lines[&5].hits, 3);
+        let functions = profiler.functions.borrow();
+        assert_eq!(functions["add"].hits, 3);
+        assert_eq!(functions[SCRIPT].hits, 1);
+    }
+
+    #[test]
+    fn the_report_lists_every_line_and_function() {
+        let report = profile().report();
+        assert!(report.starts_with("  line       hits      exprs     total ms      self ms  source\n"));
+        assert!(report.lines().any(|l| l.starts_with("     2          3          9") && l.ends_with("return a + b;")));
+        assert!(report.lines().any(|l| l.starts_with("add                           3")));
+    }
+
+    #[test]
+    fn collapsed_stacks_put_calls_between_their_caller_and_body() {
+        let stacks = profile().collapsed_stacks();
+        let frames: Vec<&str> = stacks.lines().map(|l| l.rsplit_once(' ').unwrap().0).collect();
+        assert_eq!(frames, ["<script>;line 1", "<script>;line 4", "<script>;line 4;line 5", "<script>;line 4;line 5;add;line 2"]);
+    }
+}
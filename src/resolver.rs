@@ -0,0 +1,224 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::expr::{Visitor, Assign, Expr, Binary, Grouping, Unary, Variable, walk_expr, Logical, Call, Get, Index, Update, Conditional, Sequence, Lambda};
+use crate::stmt::{Stmt, Visitor as StmtVisitor, Expression, Print, walk_stmt, Variable as StmVariable, Block, If, While, Test, Function, Return};
+use crate::token::{Literal, Token};
+
+// Works out which declaration every variable name in a program refers to. Editor tooling
+// uses it to jump between a name and where it was declared.
+pub struct Resolver {
+    scopes: RefCell<Vec<HashMap<String, usize>>>, // name -> index into declarations
+    declarations: RefCell<Vec<Declaration>>,
+    references: RefCell<Vec<Reference>>,
+}
+
+pub struct Declaration {
+    pub name: Token,
+    pub depth: usize, // 0 for globals
+}
+
+pub struct Reference {
+    pub name: Token,
+    pub declaration: Option<usize>,
+}
+
+pub struct Resolution {
+    pub declarations: Vec<Declaration>,
+    pub references: Vec<Reference>,
+}
+
+impl Resolution {
+    pub fn declaration_at(&self, line: usize, column: usize) -> Option<usize> {
+        // the declaration behind whatever name sits at this spot, be it a use or the declaration itself
+        let covers = |t: &Token| t.line == line && (t.column..=t.column + t.lexeme.chars().count()).contains(&column);
+        if let Some(index) = self.declarations.iter().position(|d| covers(&d.name)) {
+            return Some(index);
+        }
+        self.references.iter().find(|r| covers(&r.name)).and_then(|r| r.declaration)
+    }
+
+    pub fn references_to(&self, declaration: usize) -> impl Iterator<Item = &Token> {
+        self.references.iter().filter(move |r| r.declaration == Some(declaration)).map(|r| &r.name)
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_expression(&self, e: &Expression) {
+        self.resolve_expr(&e.expression);
+    }
+
+    fn visit_print(&self, e: &Print) {
+        self.resolve_expr(&e.expression);
+    }
+
+    fn visit_var_stmt(&self, e: &StmVariable) {
+        self.resolve_expr(&e.initializer);
+        self.declare(&e.name);
+    }
+
+    fn visit_block_stmt(&self, e: &Block) {
+        self.scopes.borrow_mut().push(HashMap::new());
+        self.resolve_stmts(&e.statements);
+        self.scopes.borrow_mut().pop();
+    }
+
+    fn visit_if_stmt(&self, e: &If) {
+        self.resolve_expr(&e.condition);
+        walk_stmt(self, &e.then_branch);
+        if let Some(else_branch) = &e.else_branch {
+            walk_stmt(self, else_branch);
+        }
+    }
+
+    fn visit_while_stmt(&self, e: &While) {
+        self.resolve_expr(&e.condition);
+        walk_stmt(self, &e.body);
+    }
+
+    fn visit_test_stmt(&self, e: &Test) {
+        self.scopes.borrow_mut().push(HashMap::new());
+        self.resolve_stmts(&e.body);
+        self.scopes.borrow_mut().pop();
+    }
+
+    fn visit_function_stmt(&self, e: &Function) {
+        // declared before the body is resolved, so the function can call itself
+        self.declare(&e.name);
+        self.resolve_function(&e.lambda);
+    }
+
+    fn visit_return_stmt(&self, e: &Return) {
+        if let Some(value) = &e.value {
+            self.resolve_expr(value);
+        }
+    }
+}
+
+impl Visitor<()> for Resolver {
+    fn visit_binaryexp(&self, e: &Binary) {
+        self.resolve_expr(&e.left);
+        self.resolve_expr(&e.right);
+    }
+
+    fn visit_groupingexp(&self, e: &Grouping) {
+        self.resolve_expr(&e.expression);
+    }
+
+    fn visit_literalexp(&self, _e: &Literal) {}
+
+    fn visit_unaryexp(&self, e: &Unary) {
+        self.resolve_expr(&e.right);
+    }
+
+    fn visit_variableexp(&self, e: &Variable) {
+        self.reference(&e.name);
+    }
+
+    fn visit_assignexp(&self, e: &Assign) {
+        self.resolve_expr(&e.value);
+        self.reference(&e.name);
+    }
+
+    fn visit_updateexp(&self, e: &Update) {
+        if let Some(value) = &e.value {
+            self.resolve_expr(value);
+        }
+        self.reference(&e.name);
+    }
+
+    fn visit_conditionalexp(&self, e: &Conditional) {
+        self.resolve_expr(&e.condition);
+        self.resolve_expr(&e.then_branch);
+        self.resolve_expr(&e.else_branch);
+    }
+
+    fn visit_sequenceexp(&self, e: &Sequence) {
+        self.resolve_expr(&e.left);
+        self.resolve_expr(&e.right);
+    }
+
+    fn visit_logicalexp(&self, e: &Logical) {
+        self.resolve_expr(&e.left);
+        self.resolve_expr(&e.right);
+    }
+
+    fn visit_callexp(&self, e: &Call) {
+        self.resolve_expr(&e.callee);
+        for argument in &e.arguments {
+            self.resolve_expr(&argument.value);
+        }
+    }
+
+    fn visit_lambdaexp(&self, e: &Lambda) {
+        self.resolve_function(e);
+    }
+
+    fn visit_getexp(&self, e: &Get) {
+        self.resolve_expr(&e.object);
+    }
+
+    fn visit_indexexp(&self, e: &Index) {
+        self.resolve_expr(&e.object);
+        self.resolve_expr(&e.index);
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: RefCell::new(vec![HashMap::new()]),
+            declarations: RefCell::new(Vec::new()),
+            references: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn resolve(self, stmts: &[Stmt]) -> Resolution {
+        self.resolve_stmts(stmts);
+
+        // globals are looked up when the code runs, so a name used before its `var` still
+        // means that global
+        let globals = self.scopes.borrow_mut().swap_remove(0);
+        let mut references = self.references.take();
+        for reference in references.iter_mut().filter(|r| r.declaration.is_none()) {
+            reference.declaration = globals.get(&reference.name.lexeme).copied();
+        }
+
+        Resolution { declarations: self.declarations.take(), references }
+    }
+
+    fn resolve_stmts(&self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            walk_stmt(self, stmt);
+        }
+    }
+
+    fn resolve_function(&self, lambda: &Lambda) {
+        self.scopes.borrow_mut().push(HashMap::new());
+        // a default can use the parameters before it, but not its own or later ones
+        for param in lambda.params.iter() {
+            if let Some(default) = &param.default {
+                self.resolve_expr(default);
+            }
+            self.declare(&param.name);
+        }
+        self.resolve_stmts(&lambda.body);
+        self.scopes.borrow_mut().pop();
+    }
+
+    fn resolve_expr(&self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn declare(&self, name: &Token) {
+        let mut declarations = self.declarations.borrow_mut();
+        let mut scopes = self.scopes.borrow_mut();
+        declarations.push(Declaration { name: name.clone(), depth: scopes.len() - 1 });
+        scopes.last_mut().expect("there is always a global scope").insert(name.lexeme.clone(), declarations.len() - 1);
+    }
+
+    fn reference(&self, name: &Token) {
+        let declaration = self.scopes.borrow().iter().rev().find_map(|s| s.get(&name.lexeme)).copied();
+        self.references.borrow_mut().push(Reference { name: name.clone(), declaration });
+    }
+}
@@ -1,15 +1,27 @@
-use core::f32;
-
 use crate::{report};
 use crate::token::{Token};
 use crate::token_type::TokenType;
 use crate::token::Literal;
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    trivia: Vec<Trivia>,
     start: usize,
     line: usize,
-    current: usize
+    current: usize,
+    line_start: usize, // byte offset where the current line begins
+    column: usize, // column the current token starts at, counted in characters
+    counted: usize, // byte offset column was counted up to
+    line_has_content: bool, // whether anything but whitespace was seen on the current line
+}
+
+// Things the parser doesn't care about but a formatter has to give back to the user.
+#[derive(Debug, Clone)]
+pub enum Trivia {
+    Comment { text: String, line: usize, column: usize, trailing: bool }, // trailing => code precedes it on the same line
+    BlankLine(usize),
 }
 
 impl Default for Scanner {
@@ -17,9 +29,14 @@ impl Default for Scanner {
         Scanner {
             source: String::new(),
             tokens: Vec::new(),
+            trivia: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 0,
+            counted: 0,
+            line_has_content: false,
         }
     }
 }
@@ -29,26 +46,38 @@ impl Scanner{
         Self {
             source,
             tokens: Vec::new(),
+            trivia: Vec::new(),
             start: 0,
             current: 0,
-            line: 1
+            line: 1,
+            line_start: 0,
+            column: 0,
+            counted: 0,
+            line_has_content: false,
         }
     }
 
     pub fn scan_tokens(&mut self) -> &Vec<Token> {
         while !self.is_at_end(){
             self.start = self.current;
+            self.count_columns(self.start);
             self.scan_token();
         }
-        self.tokens.push(Token::new(TokenType::Eof, "", Literal::Nil, self.line));
+        self.count_columns(self.current);
+        self.tokens.push(Token::new(TokenType::Eof, "", Literal::Nil, self.line, self.column));
 
         &self.tokens
     }
 
+    pub fn trivia(&self) -> &Vec<Trivia> {
+        &self.trivia
+    }
+
     fn add_token(&mut self, token_type: TokenType, literal: Literal){
         let text = &self.source[self.start..self.current];
-        let token: Token = Token::new(token_type, &text, literal, self.line);
+        let token: Token = Token::new(token_type, text, literal, self.line, self.column);
         self.tokens.push(token);
+        self.line_has_content = true;
     }
 
     fn scan_token(&mut self){
@@ -59,12 +88,67 @@ impl Scanner{
             ')' => self.add_token(TokenType::RightParen, Literal::Nil),
             '{' => self.add_token(TokenType::LeftBrace, Literal::Nil),
             '}' => self.add_token(TokenType::RightBrace, Literal::Nil),
+            '[' => self.add_token(TokenType::LeftBracket, Literal::Nil),
+            ']' => self.add_token(TokenType::RightBracket, Literal::Nil),
             ',' => self.add_token(TokenType::Comma, Literal::Nil),
-            '.' => self.add_token(TokenType::Dot, Literal::Nil),
-            '-' => self.add_token(TokenType::Minus, Literal::Nil),
-            '+' => self.add_token(TokenType::Plus, Literal::Nil),
+            '.' => {
+                // `...` before a rest parameter
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance_char();
+                    self.advance_char();
+                    self.add_token(TokenType::Ellipsis, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Dot, Literal::Nil);
+                }
+            }
+            '-' => {
+                if self.match_char('='){
+                    self.add_token(TokenType::MinusEqual, Literal::Nil);
+                } else if self.match_char('-'){
+                    self.add_token(TokenType::MinusMinus, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Minus, Literal::Nil);
+                }
+            }
+            '+' => {
+                if self.match_char('='){
+                    self.add_token(TokenType::PlusEqual, Literal::Nil);
+                } else if self.match_char('+'){
+                    self.add_token(TokenType::PlusPlus, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Plus, Literal::Nil);
+                }
+            }
             ';' => self.add_token(TokenType::Semicolon, Literal::Nil),
-            '*' => self.add_token(TokenType::Star, Literal::Nil),
+            '*' => {
+                if self.match_char('*'){
+                    self.add_token(TokenType::StarStar, Literal::Nil);
+                } else if self.match_char('='){
+                    self.add_token(TokenType::StarEqual, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Star, Literal::Nil);
+                }
+            }
+            '%' => {
+                if self.match_char('='){
+                    self.add_token(TokenType::PercentEqual, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Percent, Literal::Nil);
+                }
+            }
+            '&' => self.add_token(TokenType::Ampersand, Literal::Nil),
+            '|' => self.add_token(TokenType::Pipe, Literal::Nil),
+            '^' => self.add_token(TokenType::Caret, Literal::Nil),
+            '?' => self.add_token(TokenType::Question, Literal::Nil),
+            ':' => self.add_token(TokenType::Colon, Literal::Nil),
+            // integer division, `//` is already taken by comments
+            '~' => {
+                if self.match_char('/'){
+                    self.add_token(TokenType::TildeSlash, Literal::Nil);
+                } else {
+                    self.add_token(TokenType::Tilde, Literal::Nil);
+                }
+            }
             '!' => {
                 if self.match_char('='){
                     self.add_token(TokenType::BangEqual, Literal::Nil);
@@ -75,6 +159,8 @@ impl Scanner{
             '=' => {
                 if self.match_char('='){
                     self.add_token(TokenType::EqualEqual, Literal::Nil);
+                } else if self.match_char('>'){
+                    self.add_token(TokenType::Arrow, Literal::Nil);
                 } else {
                     self.add_token(TokenType::Equal, Literal::Nil);
                 }
@@ -82,6 +168,8 @@ impl Scanner{
             '<' => {
                 if self.match_char('='){
                     self.add_token(TokenType::LessEqual, Literal::Nil);
+                } else if self.match_char('<'){
+                    self.add_token(TokenType::LessLess, Literal::Nil);
                 } else {
                     self.add_token(TokenType::Less, Literal::Nil);
                 }
@@ -89,6 +177,8 @@ impl Scanner{
             '>' => {
                 if self.match_char('='){
                     self.add_token(TokenType::GreaterEqual, Literal::Nil);
+                } else if self.match_char('>'){
+                    self.add_token(TokenType::GreaterGreater, Literal::Nil);
                 } else {
                     self.add_token(TokenType::Greater, Literal::Nil);
                 }
@@ -99,17 +189,30 @@ impl Scanner{
                     while !self.is_at_end() && self.peek() != '\n'{
                         self.advance_char();
                     }
+                    self.add_comment(self.line, self.column);
                 } else if self.match_char('*'){
                     // ignore block comments => /* */
-                    while !self.is_at_end() && !(self.match_char('*') && self.match_char('/')) {
-                        self.advance_char();
+                    let (line, column) = (self.line, self.column);
+                    while !self.is_at_end() && (self.peek() != '*' || self.peek_next() != '/') {
+                        if self.advance_char() == Some('\n') { self.newline() }
                     }
+                    self.match_char('*');
+                    self.match_char('/');
+                    self.add_comment(line, column);
+                } else if self.match_char('='){
+                    self.add_token(TokenType::SlashEqual, Literal::Nil);
                 } else {
                     self.add_token(TokenType::Slash, Literal::Nil);
                 }
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line+=1,
+            '\n' => {
+                if !self.line_has_content {
+                    self.trivia.push(Trivia::BlankLine(self.line));
+                }
+                self.newline();
+                self.line_has_content = false;
+            }
             '"' => self.string(),
             _ => {
                 if self.is_digit(&c) {
@@ -126,6 +229,29 @@ impl Scanner{
 
     }
 
+    fn count_columns(&mut self, offset: usize) {
+        // the column at offset, counted on from the last token rather than from the start of the
+        // line, which would take quadratic time on a long one
+        if self.counted < self.line_start {
+            (self.column, self.counted) = (0, self.line_start);
+        }
+        self.column += self.source[self.counted..offset].chars().count();
+        self.counted = offset;
+    }
+
+    fn newline(&mut self) {
+        // call right after consuming a '\n'
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn add_comment(&mut self, line: usize, column: usize) {
+        let trailing = self.tokens.last().is_some_and(|t| t.line == line);
+        let text = self.source[self.start..self.current].trim_end().to_string();
+        self.trivia.push(Trivia::Comment { text, line, column, trailing });
+        self.line_has_content = true;
+    }
+
     fn peek(&self) -> char {
         if self.is_at_end(){return '\0'}
         self.source[self.current..].chars().next().unwrap()
@@ -137,10 +263,7 @@ impl Scanner{
         let mut iter = self.source[self.current..].chars();
         iter.next(); // skip first next character
         let next = iter.next();
-        match next{
-            None =>  '\0',
-            Some(i) => i
-        }
+        next.unwrap_or('\0')
     }
 
     fn advance_char(&mut self) -> Option<char> {
@@ -167,8 +290,7 @@ impl Scanner{
     fn string(&mut self) {
         // find closing "
         while !self.is_at_end() && self.peek() != '"'{
-            if self.peek() == '\n'{ self.line+=1 }
-            self.advance_char();
+            if self.advance_char() == Some('\n') { self.newline() }
         }
 
         if self.is_at_end(){ report(&self.line, "", "Undetermined string");}
@@ -183,24 +305,44 @@ impl Scanner{
     }
 
     fn is_digit(&self, c: &char) -> bool{
-        return *c >= '0' && *c <= '9'
+        *c >= '0' && *c <= '9'
     }
 
     fn number(&mut self){
-        // A number could be a floating 1.2344 -> only one '.' so this advances until met
-        while self.is_digit(&self.peek()) {
-            self.advance_char();
-        }
-
-        if self.peek() == '.' && self.is_digit(&self.peek_next()){
-            self.advance_char();
-
-            while self.is_digit(&self.peek()) {
+        // Takes in everything that could be part of the number, letters included, so `0b102` or
+        // `12px` is reported as one malformed number rather than scanning as a number and then
+        // something else. number_literal works out what was meant.
+        let radix_prefix = self.source[self.start..].starts_with('0')
+            && matches!(self.peek(), 'x' | 'X' | 'b' | 'B' | 'o' | 'O');
+        let mut fraction_allowed = !radix_prefix;
+        loop {
+            let c = self.peek();
+            if self.is_alpha_numeric(&c) {
+                self.advance_char();
+                if !radix_prefix && (c == 'e' || c == 'E') {
+                    fraction_allowed = false;
+                    // the exponent's sign, 1e-9
+                    if matches!(self.peek(), '+' | '-') && self.is_digit(&self.peek_next()) {
+                        self.advance_char();
+                    }
+                }
+            } else if c == '.' && fraction_allowed && self.is_digit(&self.peek_next()) {
                 self.advance_char();
+                fraction_allowed = false;
+            } else {
+                break
             }
         }
 
-        self.add_token(TokenType::Number, Literal::Number(self.source[self.start..self.current].parse::<f64>().unwrap()));
+        let text = &self.source[self.start..self.current];
+        match number_literal(text) {
+            Ok(literal) => self.add_token(TokenType::Number, literal),
+            Err(message) => {
+                report(&self.line, &format!(" at '{}'", text), &message);
+                // a stand-in so parsing carries on and can report whatever else is wrong
+                self.add_token(TokenType::Number, Literal::Integer(0));
+            }
+        }
     }
 
     fn identifier(&mut self){
@@ -214,10 +356,154 @@ impl Scanner{
     }
 
     fn is_alpha(&self, c: &char) -> bool{
-        return (*c >= 'a' && *c <= 'z') || (*c >= 'A' && *c <= 'Z') || (*c == '_')
+        (*c >= 'a' && *c <= 'z') || (*c >= 'A' && *c <= 'Z') || (*c == '_')
     }
 
     fn is_alpha_numeric(&self, c: &char) -> bool{
-        return self.is_alpha(c) || self.is_digit(c)
+        self.is_alpha(c) || self.is_digit(c)
+    }
+}
+
+fn number_literal(text: &str) -> Result<Literal, String> {
+    // The value of a number as written:
+    //     255, 0xff, 0b1111_1111, 0o377    integers, big integers when they don't fit an i64
+    //     2.5, 25e-1, 1_000.5              floats, any number with a fraction or an exponent
+    //     2.50d, 1e3d                      exact decimals
+    // and otherwise a message saying what's wrong with it.
+    let radix = match text.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0b" | "0B") => 2,
+        Some("0o" | "0O") => 8,
+        _ => 10,
+    };
+    if radix != 10 {
+        let digits = separated(&text[2..])?;
+        if digits.is_empty() {
+            return Err(format!("Expected digits after '{}'.", &text[..2]))
+        }
+        if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+            return Err(format!("'{}' is not a base {} digit.", c, radix))
+        }
+        return Ok(match i64::from_str_radix(&digits, radix) {
+            Ok(n) => Literal::Integer(n),
+            Err(_) => Literal::BigInt(BigInt::parse_radix(&digits, radix).expect("the digits were checked")),
+        })
+    }
+
+    let (text, decimal) = match text.strip_suffix('d') {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (separated(whole)?, Some(separated(fraction)?)),
+        None => (separated(mantissa)?, None),
+    };
+    let exponent = match exponent {
+        Some(exponent) => {
+            let (sign, digits) = match exponent.strip_prefix(['+', '-']) {
+                Some(digits) => (&exponent[..1], digits),
+                None => ("", exponent),
+            };
+            let digits = separated(digits)?;
+            if digits.is_empty() {
+                return Err("Expected digits in the exponent.".to_string())
+            }
+            Some(format!("{}{}", sign, digits))
+        }
+        None => None,
+    };
+    let digits = [Some(&whole), fraction.as_ref(), exponent.as_ref()];
+    if let Some(c) = digits.iter().flatten().flat_map(|d| d.chars()).find(|c| !c.is_ascii_digit() && *c != '-' && *c != '+') {
+        return Err(format!("'{}' is not a digit.", c))
+    }
+
+    let mantissa = match &fraction {
+        Some(fraction) => format!("{}.{}", whole, fraction),
+        None => whole,
+    };
+    if decimal {
+        let exponent = match &exponent {
+            Some(exponent) => exponent.parse::<i32>().ok().filter(|e| e.abs() <= 10_000)
+                .ok_or("The exponent of a decimal can be at most 10000.")?,
+            None => 0,
+        };
+        let value = Decimal::parse(&mantissa).expect("the digits were checked");
+        return Ok(Literal::Decimal(value.times_ten_to(exponent)))
+    }
+    if fraction.is_none() && exponent.is_none() {
+        return Ok(match mantissa.parse::<i64>() {
+            Ok(n) => Literal::Integer(n),
+            Err(_) => Literal::BigInt(BigInt::parse(&mantissa).expect("the digits were checked")),
+        })
+    }
+    let float = match &exponent {
+        Some(exponent) => format!("{}e{}", mantissa, exponent),
+        None => mantissa,
+    };
+    match float.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Literal::Number(n)),
+        _ => Err("Number is too large for a float.".to_string()),
+    }
+}
+
+fn separated(digits: &str) -> Result<String, String> {
+    // digits with the `_` separators taken out, which may only sit between two digits
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err("'_' can only go between digits.".to_string())
+    }
+    Ok(digits.replace('_', ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_numbers_are_integers_and_fractions_floats() {
+        assert!(matches!(number_literal("42"), Ok(Literal::Integer(42))));
+        assert!(matches!(number_literal("42.0"), Ok(Literal::Number(n)) if n == 42.0));
+        assert!(matches!(number_literal("9223372036854775808"), Ok(Literal::BigInt(_))));
+    }
+
+    #[test]
+    fn radix_prefixes_exponents_and_separators() {
+        assert!(matches!(number_literal("0xFF"), Ok(Literal::Integer(255))));
+        assert!(matches!(number_literal("0b1010"), Ok(Literal::Integer(10))));
+        assert!(matches!(number_literal("0o755"), Ok(Literal::Integer(493))));
+        assert!(matches!(number_literal("1_000_000"), Ok(Literal::Integer(1_000_000))));
+        assert!(matches!(number_literal("1e-9"), Ok(Literal::Number(n)) if n == 1e-9));
+        assert!(matches!(number_literal("1_5.2_5E+1_0"), Ok(Literal::Number(n)) if n == 15.25e10));
+        assert!(matches!(number_literal("0xffff_ffff_ffff_ffff"), Ok(Literal::BigInt(_))));
+    }
+
+    #[test]
+    fn malformed_numbers_say_what_is_wrong() {
+        let error = |text| number_literal(text).err();
+        assert_eq!(error("0x").as_deref(), Some("Expected digits after '0x'."));
+        assert_eq!(error("1__0").as_deref(), Some("'_' can only go between digits."));
+        assert_eq!(error("1_").as_deref(), Some("'_' can only go between digits."));
+        assert_eq!(error("1e").as_deref(), Some("Expected digits in the exponent."));
+        assert_eq!(error("0b102").as_deref(), Some("'2' is not a base 2 digit."));
+        assert_eq!(error("12px").as_deref(), Some("'p' is not a digit."));
+        assert_eq!(error("1e400").as_deref(), Some("Number is too large for a float."));
+    }
+
+    #[test]
+    fn a_malformed_number_is_reported_and_scanning_carries_on() {
+        let (tokens, errors) = crate::collect_diagnostics(|| Scanner::new("print 0x;\nprint 1e;".to_string()).scan_tokens().clone());
+        let errors: Vec<(usize, String)> = errors.into_iter().map(|e| (e.line, e.message)).collect();
+        assert_eq!(errors, [(1, "Expected digits after '0x'.".to_string()), (2, "Expected digits in the exponent.".to_string())]);
+        assert_eq!(tokens.len(), 7); // both statements, with a stand-in for each number, and the end
+    }
+
+    #[test]
+    fn columns_count_characters_from_the_start_of_each_line() {
+        let tokens = Scanner::new("var a = \"é\";\n  print a;".to_string()).scan_tokens().clone();
+        let columns: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line, t.column)).collect();
+        assert_eq!(columns, [(1, 0), (1, 4), (1, 6), (1, 8), (1, 11), (2, 2), (2, 8), (2, 9), (2, 10)]);
     }
 }
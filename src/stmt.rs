@@ -1,5 +1,5 @@
 use crate::token::{Token};
-use crate::expr::Expr;
+use crate::expr::{Expr, Lambda};
 
 pub enum Stmt {
     Expression(Expression),
@@ -7,15 +7,20 @@ pub enum Stmt {
     Variable(Variable),
     Block(Block),
     If(If),
-    While(While)
+    While(While),
+    Test(Test),
+    Function(Function),
+    Return(Return),
 }
 
 pub struct Expression {
     pub expression: Expr,
+    pub line: usize,
 }
 
 pub struct Print {
     pub expression: Expr,
+    pub line: usize,
 }
 
 pub struct Variable {
@@ -24,18 +29,67 @@ pub struct Variable {
 }
 
 pub struct Block {
-    pub statements: Vec<Stmt>
+    pub statements: Vec<Stmt>,
+    pub line: usize,
+    pub end_line: usize, // line of the closing '}'
 }
 
 pub struct If {
     pub condition: Expr,
     pub then_branch: Box<Stmt>,
-    pub else_branch: Option<Box<Stmt>>
+    pub else_branch: Option<Box<Stmt>>,
+    pub line: usize,
 }
 
 pub struct While {
     pub condition: Expr,
-    pub body: Box<Stmt>
+    pub body: Box<Stmt>,
+    pub line: usize,
+    // Some when this loop is a desugared `for`. It remembers which clauses the user
+    // actually wrote so tools can tell them apart from the ones the parser filled in.
+    pub for_loop: Option<ForLoop>,
+}
+
+#[derive(Clone, Copy)]
+pub struct ForLoop {
+    pub initializer: bool,
+    pub condition: bool,
+    pub increment: bool,
+}
+
+// `test "name" { ... }`, only run by `rlox test --unit`
+pub struct Test {
+    pub name: Token, // the string token
+    pub body: Vec<Stmt>,
+    pub line: usize,
+    pub end_line: usize,
+}
+
+// `fun name(params) { body }`
+pub struct Function {
+    pub name: Token,
+    pub lambda: Lambda,
+}
+
+pub struct Return {
+    pub keyword: Token,
+    pub value: Option<Expr>,
+}
+
+impl Stmt {
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Expression(e) => e.line,
+            Stmt::Print(p) => p.line,
+            Stmt::Variable(v) => v.name.line,
+            Stmt::Block(b) => b.line,
+            Stmt::If(i) => i.line,
+            Stmt::While(w) => w.line,
+            Stmt::Test(t) => t.line,
+            Stmt::Function(f) => f.name.line,
+            Stmt::Return(r) => r.keyword.line,
+        }
+    }
 }
 
 pub trait Visitor<T> {
@@ -45,6 +99,9 @@ pub trait Visitor<T> {
     fn visit_block_stmt(&self, e: &Block) -> T;
     fn visit_if_stmt(&self, e: &If) -> T;
     fn visit_while_stmt(&self, e: &While) -> T;
+    fn visit_test_stmt(&self, e: &Test) -> T;
+    fn visit_function_stmt(&self, e: &Function) -> T;
+    fn visit_return_stmt(&self, e: &Return) -> T;
 }
 
 
@@ -55,6 +112,9 @@ pub fn walk_stmt<T>(visitor: &dyn Visitor<T>, e: &Stmt) -> T {
         Stmt::Variable(var) => visitor.visit_var_stmt(var),
         Stmt::Block(blo) => visitor.visit_block_stmt(blo),
         Stmt::If(i) => visitor.visit_if_stmt(i),
-        Stmt::While(whi) => visitor.visit_while_stmt(whi)
+        Stmt::While(whi) => visitor.visit_while_stmt(whi),
+        Stmt::Test(test) => visitor.visit_test_stmt(test),
+        Stmt::Function(function) => visitor.visit_function_stmt(function),
+        Stmt::Return(ret) => visitor.visit_return_stmt(ret),
     }
 }
@@ -0,0 +1,266 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::collect_diagnostics;
+use crate::interpreter::{Capabilities, Interpreter};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+// What a script says it should do, read from comments in it:
+//
+//     print a;          // expect: 10
+//     print -"a";       // expect runtime error: Operand must be a number
+//     print (1;         // Error at ';': expected right paranthesis
+//     // [line 7] Error at end: Expected ';' after value.
+//
+// The compile errors are listed in the form rlox reports them, `[line N] Error ...`.
+struct Expectations {
+    output: Vec<String>,
+    errors: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations { output: Vec::new(), errors: Vec::new(), runtime_error: None };
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let Some((_, comment)) = text.split_once("//") else { continue };
+            let comment = comment.trim();
+            if let Some(output) = comment.strip_prefix("expect:") {
+                expectations.output.push(output.trim().to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error:") {
+                expectations.runtime_error = Some(format!("[line {}] {}", line, message.trim()));
+            } else if comment.starts_with("[line ") {
+                expectations.errors.push(comment.to_string());
+            } else if comment.starts_with("Error") {
+                expectations.errors.push(format!("[line {}] {}", line, comment));
+            }
+        }
+        expectations
+    }
+
+    fn exit_code(&self) -> i32 {
+        // the codes rlox exits with, see run_script
+        if !self.errors.is_empty() {
+            64
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+// Runs every script under the given paths in its own rlox process and checks what it printed
+// against the expectations written in it. Prints a line per script and a summary, returns
+// whether everything passed.
+pub fn run_golden(paths: &[String]) -> io::Result<bool> {
+    let rlox = std::env::current_exe()?;
+    let mut scripts = Vec::new();
+    for path in paths {
+        collect_scripts(Path::new(path), true, &mut scripts)?;
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for script in scripts {
+        let failures = check(&rlox, &script)?;
+        if failures.is_empty() {
+            println!("PASS {}", script.display());
+            passed += 1;
+            continue;
+        }
+        println!("FAIL {}", script.display());
+        for failure in failures {
+            for line in failure.lines() {
+                println!("    {}", line);
+            }
+        }
+        failed += 1;
+    }
+    println!();
+    println!("{} passed, {} failed", passed, failed);
+    Ok(failed == 0)
+}
+
+// Runs the `test "name" { ... }` blocks of every script under the given paths. A script's own code
+// runs first, so its tests can use whatever it declares, then every test runs in a scope of its own.
+// A failing test doesn't stop the ones after it. Returns whether everything passed.
+pub fn run_unit(paths: &[String]) -> io::Result<bool> {
+    let mut scripts = Vec::new();
+    for path in paths {
+        collect_scripts(Path::new(path), true, &mut scripts)?;
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for script in scripts {
+        println!("{}", script.display());
+        let source = fs::read_to_string(&script)?;
+        let (statements, errors) = collect_diagnostics(|| {
+            let tokens = Scanner::new(source).scan_tokens().clone();
+            Parser::new(tokens).parse()
+        });
+        let Some(statements) = statements.filter(|_| errors.is_empty()) else {
+            for error in errors {
+                println!("    [line {}] {}", error.line, error.message);
+            }
+            failed += 1;
+            continue;
+        };
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_stack_size(crate::CALL_STACK_SIZE);
+        interpreter.set_capabilities(Capabilities { io: true });
+        if let Err(err) = interpreter.try_interpret(&statements) {
+            println!("    {}", err);
+            failed += 1;
+            continue;
+        }
+        for stmt in &statements {
+            let Stmt::Test(test) = stmt else { continue };
+            let name = test.name.literal.to_string();
+            match interpreter.run_test(test) {
+                Ok(()) => {
+                    println!("    ok      {}", name);
+                    passed += 1;
+                }
+                Err(err) => {
+                    println!("    FAILED  {}", name);
+                    println!("            {}", err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!();
+    println!("{} passed, {} failed", passed, failed);
+    Ok(failed == 0)
+}
+
+fn collect_scripts(path: &Path, named: bool, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    // files named on the command line run whatever they're called, in directories only `.lox` files do
+    if !path.is_dir() {
+        if named || path.extension().is_some_and(|e| e == "lox") {
+            scripts.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        collect_scripts(&entry, false, scripts)?;
+    }
+    Ok(())
+}
+
+fn check(rlox: &Path, script: &Path) -> io::Result<Vec<String>> {
+    let expectations = Expectations::parse(&fs::read_to_string(script)?);
+    let result = Command::new(rlox).arg(script).output()?;
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let output: Vec<&str> = stdout.lines().collect();
+    let errors: Vec<&str> = stderr.lines().collect();
+
+    let mut failures = Vec::new();
+    let expected_output: Vec<&str> = expectations.output.iter().map(String::as_str).collect();
+    if output != expected_output {
+        failures.push(format!("output differs (- expected, + actual):\n{}", diff(&expected_output, &output)));
+    }
+
+    let mut expected_errors: Vec<&str> = expectations.errors.iter().map(String::as_str).collect();
+    expected_errors.extend(expectations.runtime_error.as_deref());
+    if errors != expected_errors {
+        failures.push(format!("errors differ (- expected, + actual):\n{}", diff(&expected_errors, &errors)));
+    }
+
+    let code = result.status.code().unwrap_or(-1);
+    if code != expectations.exit_code() {
+        failures.push(format!("exited with {}, expected {}", code, expectations.exit_code()));
+    }
+    Ok(failures)
+}
+
+fn diff(expected: &[&str], actual: &[&str]) -> String {
+    // a line diff over the longest common subsequence, fine for the few lines a script prints
+    let (n, m) = (expected.len(), actual.len());
+    let mut common = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations_are_read_from_comments() {
+        let expectations = Expectations::parse("\
+print a;          // expect: 10
+print -\"a\";       // expect runtime error: Operand must be a number
+print (1;         // Error at ';': expected right paranthesis
+// [line 7] Error at end: Expected ';' after value.
+");
+        assert_eq!(expectations.output, ["10"]);
+        assert_eq!(expectations.runtime_error.as_deref(), Some("[line 2] Operand must be a number"));
+        assert_eq!(expectations.errors, [
+            "[line 3] Error at ';': expected right paranthesis",
+            "[line 7] Error at end: Expected ';' after value.",
+        ]);
+        assert_eq!(expectations.exit_code(), 64);
+    }
+
+    #[test]
+    fn the_exit_code_follows_the_worst_expectation() {
+        assert_eq!(Expectations::parse("print 1; // expect: 1").exit_code(), 0);
+        assert_eq!(Expectations::parse("print -nil; // expect runtime error: Operand must be a number").exit_code(), 70);
+    }
+
+    #[test]
+    fn diff_marks_missing_and_extra_lines() {
+        assert_eq!(diff(&["1", "2", "3"], &["1", "3", "4"]), "  1\n- 2\n  3\n+ 4");
+        assert_eq!(diff(&[], &["a"]), "+ a");
+    }
+
+    #[test]
+    fn directories_contribute_only_their_lox_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("rlox-tests-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b.lox", "a.lox", "notes.txt", "nested/c.lox"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let mut scripts = Vec::new();
+        collect_scripts(&dir, true, &mut scripts).unwrap();
+        collect_scripts(&dir.join("notes.txt"), true, &mut scripts).unwrap();
+        let names: Vec<&Path> = scripts.iter().map(|s| s.strip_prefix(&dir).unwrap()).collect();
+        assert_eq!(names, [Path::new("a.lox"), Path::new("b.lox"), Path::new("nested/c.lox"), Path::new("notes.txt")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
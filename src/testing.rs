@@ -0,0 +1,24 @@
+// Helpers shared by the unit tests next to each module.
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+pub fn parse(source: &str) -> Option<Vec<Stmt>> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens().clone();
+    Parser::new(tokens).parse()
+}
+
+pub fn run(source: &str) -> Result<String, String> {
+    // what the script printed, or the runtime error it stopped with
+    let statements = parse(source).expect("the script parses");
+    let (interpreter, printed) = Interpreter::capturing();
+    interpreter.try_interpret(&statements).map_err(|err| err.to_string())?;
+    Ok(printed.take())
+}
+
+pub fn on_script_thread<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    // with as much stack as main gives scripts, parsing deeply nested code in a debug build takes
+    // far more than a test's own thread has
+    std::thread::Builder::new().stack_size(crate::STACK_SIZE).spawn(f).unwrap().join().unwrap()
+}
@@ -1,3 +1,6 @@
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
+use crate::object::format_float;
 use crate::token_type::TokenType;
 use std::fmt;
 
@@ -7,11 +10,15 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Literal,
     pub line: usize,
+    pub column: usize, // where the lexeme starts on its line, counted in characters from 0
 }
 
 #[derive(Debug, Clone)]
 pub enum Literal {
     Number(f64),
+    Integer(i64),
+    BigInt(BigInt),    // an integer literal too large for an i64
+    Decimal(Decimal),  // `1.10d`
     String(String),
     Bool(bool),
     Nil,
@@ -20,7 +27,10 @@ pub enum Literal {
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Literal::Number(n) => write!(f, "{}", n),
+            Literal::Number(n) => write!(f, "{}", format_float(*n)),
+            Literal::Integer(n) => write!(f, "{}", n),
+            Literal::BigInt(n) => write!(f, "{}", n),
+            Literal::Decimal(n) => write!(f, "{}d", n),
             Literal::String(s) => write!(f, "{}", s),
             Literal::Bool(b) => write!(f, "{}", b),
             Literal::Nil => write!(f, "nil"),
@@ -29,12 +39,13 @@ impl fmt::Display for Literal {
 }
 
 impl Token {
-    pub fn new(kind: TokenType, lexeme: &str, literal: Literal, line: usize) -> Self{
+    pub fn new(kind: TokenType, lexeme: &str, literal: Literal, line: usize, column: usize) -> Self{
         Self{
             kind,
             lexeme: lexeme.to_string(),
             literal,
-            line
+            line,
+            column
         }
     }
 
@@ -43,7 +54,8 @@ impl Token {
             kind: TokenType::Nil,
             lexeme: "".to_string(),
             literal: Literal::Nil,
-            line: line
+            line,
+            column: 0
         }
     }
 }
@@ -4,14 +4,21 @@
 #[derive(PartialEq)]
 pub enum TokenType{
     // Single-character tokens.
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Comma, Dot, Minus, Plus, Semicolon, Slash, Star, Percent,
+    Ampersand, Pipe, Caret, Question, Colon,
 
     // One or two character tokens.
     Bang, BangEqual,
-    Equal, EqualEqual,
-    Greater, GreaterEqual,
-    Less, LessEqual,
+    Equal, EqualEqual, Arrow,
+    Greater, GreaterEqual, GreaterGreater,
+    Less, LessEqual, LessLess,
+    StarStar, Tilde, TildeSlash,
+    PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual,
+    PlusPlus, MinusMinus,
+
+    // Three character tokens.
+    Ellipsis,
 
     // Literals.
     Identifier, String, Number,
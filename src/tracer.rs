@@ -0,0 +1,117 @@
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use crate::ast_printer::AstPrinter;
+use crate::expr::Expr;
+use crate::interpreter::{Hook, Interpreter, RuntimeError};
+use crate::object::Object;
+use crate::stmt::Stmt;
+
+// Logs execution for `--trace`: every statement as it starts, the value of every expression once
+// it's evaluated and blocks entering and leaving their environments. Lines are indented by how
+// deeply nested the environment is. Only what happens on the lines in `lines` is logged.
+pub struct Tracer {
+    output: RefCell<Box<dyn Write>>,
+    lines: RangeInclusive<usize>,
+    printer: AstPrinter,
+    running: RefCell<Vec<usize>>, // lines of the statements currently executing
+    depth: Cell<usize>,
+}
+
+impl Hook for Tracer {
+    fn before_stmt(&self, _interpreter: &Interpreter, stmt: &Stmt) -> Result<(), RuntimeError> {
+        self.running.borrow_mut().push(stmt.line());
+        self.log(&self.printer.print_head(stmt));
+        Ok(())
+    }
+
+    fn after_stmt(&self, _interpreter: &Interpreter, _stmt: &Stmt) {
+        self.running.borrow_mut().pop();
+    }
+
+    fn after_expr(&self, interpreter: &Interpreter, expr: &Expr, result: &Result<Object, RuntimeError>) {
+        let value = match result {
+            Ok(value) => interpreter.stringify(value),
+            Err(err) => format!("error: {}", err),
+        };
+        self.log(&format!("    {} => {}", self.printer.print(expr), value));
+    }
+
+    fn push_environment(&self, _interpreter: &Interpreter) {
+        self.depth.set(self.depth.get() + 1);
+        self.log(&format!("push environment (depth {})", self.depth.get()));
+    }
+
+    fn pop_environment(&self, _interpreter: &Interpreter) {
+        self.log(&format!("pop environment (depth {})", self.depth.get()));
+        self.depth.set(self.depth.get() - 1);
+    }
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, lines: RangeInclusive<usize>) -> Self {
+        Self {
+            output: RefCell::new(output),
+            lines,
+            printer: AstPrinter,
+            running: RefCell::new(Vec::new()),
+            depth: Cell::new(0),
+        }
+    }
+
+    fn log(&self, message: &str) {
+        let line = self.running.borrow().last().copied().unwrap_or_default();
+        if !self.lines.contains(&line) {
+            return;
+        }
+        let indent = "  ".repeat(self.depth.get());
+        // a trace that can't be written shouldn't take the script down with it
+        let _ = writeln!(self.output.borrow_mut(), "[line {}] {}{}", line, indent, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::interpreter::Captured;
+    use crate::testing::parse;
+
+    const SOURCE: &str = "var a = 1;\n{\n  a = a + 1;\n}\n";
+
+    fn trace(lines: RangeInclusive<usize>) -> String {
+        let traced = Captured::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(Rc::new(Tracer::new(Box::new(traced.clone()), lines)));
+        assert!(interpreter.try_interpret(&parse(SOURCE).unwrap()).is_ok());
+        traced.take()
+    }
+
+    #[test]
+    fn statements_values_and_environments_are_logged() {
+        assert_eq!(trace(1..=usize::MAX), "\
+[line 1] var a = 1
+[line 1]     1 => 1
+[line 2] {
+[line 2]   push environment (depth 1)
+[line 3]   (= a (+ a 1))
+[line 3]       a => 1
+[line 3]       1 => 1
+[line 3]       (+ a 1) => 2
+[line 3]       (= a (+ a 1)) => 2
+[line 2]   pop environment (depth 1)
+");
+    }
+
+    #[test]
+    fn only_the_chosen_lines_are_logged() {
+        assert_eq!(trace(3..=3), "\
+[line 3]   (= a (+ a 1))
+[line 3]       a => 1
+[line 3]       1 => 1
+[line 3]       (+ a 1) => 2
+[line 3]       (= a (+ a 1)) => 2
+");
+    }
+}